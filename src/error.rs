@@ -0,0 +1,71 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::io;
+
+use image::ImageError;
+
+use fractal::palette::PaletteError;
+
+//==============================================================================
+// Errors
+//==============================================================================
+
+/// Describes why rendering, loading or saving failed. Errors are reported to
+/// the user instead of ending the session.
+/// - `TooLarge` a render of `pixel_size` pixels per side would need about
+///   `bytes` of memory, more than `limit`
+/// - `BufferSize` a buffer does not hold as many bytes or points as its
+///   dimensions require
+/// - `RawData` a raw fractal data file could not be read
+#[derive(Debug)]
+pub enum Error {
+  Io(io::Error),
+  Image(ImageError),
+  Palette(PaletteError),
+  TooLarge { pixel_size : usize, bytes : u64, limit : u64 },
+  BufferSize { expected : usize, found : usize },
+  RawData(String),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Error::Io(err) => write!(f, "could not access file: {}", err),
+      Error::Image(err) => write!(f, "could not read or write image: {}", err),
+      Error::Palette(err) => write!(f, "{}", err),
+      Error::TooLarge{pixel_size, bytes, limit} =>
+        write!(f, "a {0} x {0} render needs about {1} MB, the limit is {2} MB",
+          pixel_size, bytes >> 20, limit >> 20),
+      Error::BufferSize{expected, found} =>
+        write!(f, "buffer holds {} values instead of {}", found, expected),
+      Error::RawData(message) => write!(f, "malformed raw fractal data: {}", message),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(err) => Some(err),
+      Error::Image(err) => Some(err),
+      Error::Palette(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(err : io::Error) -> Self { Error::Io(err) }
+}
+
+impl From<ImageError> for Error {
+  fn from(err : ImageError) -> Self { Error::Image(err) }
+}
+
+impl From<PaletteError> for Error {
+  fn from(err : PaletteError) -> Self { Error::Palette(err) }
+}
+
+/// Result of fallible rendering, loading and saving.
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,482 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::f64::consts::PI;
+
+use fractal::color;
+use fractal::definitions::*;
+
+//==============================================================================
+// Coloring algorithm definitions
+//==============================================================================
+
+/// Result of coloring a single fractal point. `Index` is a position in the
+/// gradient (expected between 0 and 1) and `Color` a direct BGRA color with
+/// channels on the scale [0, 1].
+#[derive (Clone, Copy, Debug)]
+pub enum ColorOutput {
+  Index(f64),
+  Color([f64; 4]),
+}
+
+/// Everything besides the fractal point that coloring algorithms may use.
+/// `slot_gradient` is the gradient belonging to the slot (exterior, interior
+/// or a layer) the algorithm is used in and `gradient` maps [0, 1] to its
+/// colors, with channels on the scale [0, 1] as given by
+/// `color::gradient_function_hdr`.
+pub struct ColoringContext<'a> {
+  pub frac_args : &'a FracArgs,
+  pub img_args : &'a ImageArgs,
+  pub slot_gradient : &'a Gradient,
+  pub gradient : &'a dyn Fn(f64) -> [f64; 4],
+}
+
+/// An algorithm that turns a fractal point into a gradient index or a color.
+/// Algorithms are listed in a registry from which the UI picks the exterior
+/// and interior coloring.
+pub trait ColoringAlgorithm : Sync {
+  /// Name of the algorithm, unique within a registry.
+  fn name(&self) -> &'static str;
+
+  /// Colors the fractal point `point`.
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput;
+
+  /// Whether the algorithm reads the attracting cycle of a point, which is
+  /// only searched for if some algorithm needs it.
+  fn uses_cycles(&self) -> bool { false }
+}
+
+/// A handle to a registered coloring algorithm. Handles are compared by the
+/// name of the algorithm.
+#[derive (Clone, Copy)]
+pub struct Algorithm(pub &'static dyn ColoringAlgorithm);
+
+impl PartialEq for Algorithm {
+  fn eq(&self, other: &Algorithm) -> bool { self.0.name() == other.0.name() }
+}
+
+impl Eq for Algorithm {}
+
+impl std::fmt::Debug for Algorithm {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Algorithm({})", self.0.name())
+  }
+}
+
+impl std::fmt::Display for Algorithm {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", self.0.name())
+  }
+}
+
+//==============================================================================
+// Registry
+//==============================================================================
+
+/// Algorithms available for points that escaped.
+pub static EXTERIOR_ALGORITHMS : &[Algorithm] = &[
+  Algorithm(&SmoothIteration),
+  Algorithm(&RawIteration),
+  Algorithm(&LogIteration),
+  Algorithm(&ContinuousPotential),
+  Algorithm(&FinalMagnitude),
+  Algorithm(&DistanceEstimate),
+  Algorithm(&OrbitTrap),
+  Algorithm(&StripeAverage),
+  Algorithm(&TriangleAverage),
+  Algorithm(&BinaryDecomposition),
+  Algorithm(&NaryDecomposition),
+  Algorithm(&FieldLines),
+  Algorithm(&ExternalAngle),
+  ];
+
+/// Algorithms available for points that never escaped.
+pub static INTERIOR_ALGORITHMS : &[Algorithm] = &[
+  Algorithm(&Flat),
+  Algorithm(&FinalMagnitude),
+  Algorithm(&FinalAngle),
+  Algorithm(&MinimumMagnitude),
+  Algorithm(&MinimumStep),
+  Algorithm(&CyclePeriod),
+  Algorithm(&CycleMultiplier),
+  Algorithm(&OrbitTrap),
+  ];
+
+/// Algorithms available for layers, which may color any point. Lists every
+/// exterior and interior algorithm once.
+pub static LAYER_ALGORITHMS : &[Algorithm] = &[
+  Algorithm(&SmoothIteration),
+  Algorithm(&RawIteration),
+  Algorithm(&LogIteration),
+  Algorithm(&ContinuousPotential),
+  Algorithm(&FinalMagnitude),
+  Algorithm(&DistanceEstimate),
+  Algorithm(&OrbitTrap),
+  Algorithm(&StripeAverage),
+  Algorithm(&TriangleAverage),
+  Algorithm(&BinaryDecomposition),
+  Algorithm(&NaryDecomposition),
+  Algorithm(&FieldLines),
+  Algorithm(&ExternalAngle),
+  Algorithm(&Flat),
+  Algorithm(&FinalAngle),
+  Algorithm(&MinimumMagnitude),
+  Algorithm(&MinimumStep),
+  Algorithm(&CyclePeriod),
+  Algorithm(&CycleMultiplier),
+  ];
+
+pub static DEFAULT_EXTERIOR : Algorithm = Algorithm(&SmoothIteration);
+pub static DEFAULT_INTERIOR : Algorithm = Algorithm(&Flat);
+
+//==============================================================================
+// Auxiliary functions
+//==============================================================================
+
+/// Returns the fraction of the last step that the escaped point `(re, im)`
+/// needed to cross `iter_bound`. Equals `1.` if the point just crossed it and
+/// falls towards `0.` the further the point jumped across.
+pub fn smooth_fraction(re : f64, im : f64, iter_bound : f64) -> f64 {
+  let abs_val = re * re + im * im;
+  let f = 1. + (iter_bound.ln() / abs_val.ln()).ln() / f64::ln(2.);
+  f.max(0.).min(1.)
+}
+
+/// Returns the smoothed iteration count of `point` (only works for points
+/// which crossed the iteration bound).
+pub fn smooth_iterations(point : &FracPoint) -> f64 {
+  let abs_val = point.re * point.re + point.im * point.im;
+  let smooth1 = abs_val.ln() / f64::ln(2.);
+  let smooth2 = smooth1.ln() / f64::ln(2.);
+  (point.n as f64) - smooth2
+}
+
+/// Returns the iteration count of `point` as a fraction of the iteration
+/// steps. The count is smoothed if the gradient of the slot asks for it and
+/// the point escaped.
+fn iteration_fraction(point : &FracPoint, ctx : &ColoringContext) -> f64 {
+  let max_steps = ctx.frac_args.steps;
+  let n =
+    if ctx.slot_gradient.smooth && point.n < max_steps {
+      smooth_iterations(point)
+    } else {
+      point.n as f64
+    };
+  n / max_steps as f64
+}
+
+/// Returns the angle of the final value of `point` as a fraction of the full
+/// turn in [0, 1).
+fn turn(point : &FracPoint) -> f64 {
+  point.im.atan2(point.re) / (2. * PI) + 0.5
+}
+
+/// Interpolates between an orbit average `avg` and the same average without
+/// the last step `avg_prev`, using the smoothing fraction of escaped points.
+fn smooth_average(point : &FracPoint, avg : f64, avg_prev : f64, args : &FracArgs)
+-> f64 {
+  if point.n < args.steps {
+    let f = smooth_fraction(point.re, point.im, args.iter_bound);
+    f * avg + (1. - f) * avg_prev
+  } else {
+    avg
+  }
+}
+
+/// Colors `x` with the gradient and darkens it by `shade` (between 0 and 1)
+/// scaled with the angle strength.
+fn shaded(x : f64, shade : f64, ctx : &ColoringContext) -> ColorOutput {
+  let [b, g, r, a] = (ctx.gradient)(x.max(0.0).min(1.0));
+  let dim = 1. - ctx.img_args.angle_strength * shade;
+  ColorOutput::Color([b * dim, g * dim, r * dim, a])
+}
+
+/// Degree of the iteration function, i.e. how fast escaping points grow.
+fn iterator_degree(kind : IteratorKind) -> f64 {
+  match kind {
+    IteratorKind::Square | IteratorKind::Inverse | IteratorKind::Ship => 2.,
+    IteratorKind::Cube => 3.,
+  }
+}
+
+//==============================================================================
+// Exterior algorithms
+//==============================================================================
+
+/// Smoothed iteration count, falls back to the raw count if the gradient
+/// is not smooth.
+pub struct SmoothIteration;
+
+impl ColoringAlgorithm for SmoothIteration {
+  fn name(&self) -> &'static str { "Smooth iteration" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(iteration_fraction(point, ctx))
+  }
+}
+
+/// Raw iteration count.
+pub struct RawIteration;
+
+impl ColoringAlgorithm for RawIteration {
+  fn name(&self) -> &'static str { "Raw iteration" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(point.n as f64 / ctx.frac_args.steps as f64)
+  }
+}
+
+/// Logarithm of the smoothed iteration count, which spreads low counts over
+/// more of the gradient.
+pub struct LogIteration;
+
+impl ColoringAlgorithm for LogIteration {
+  fn name(&self) -> &'static str { "Log iteration" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let max_steps = ctx.frac_args.steps as f64;
+    let n = smooth_iterations(point).max(0.);
+    ColorOutput::Index((1. + n).ln() / (1. + max_steps).ln())
+  }
+}
+
+/// Continuous potential `G = ln|z| / d^n` where `d` is the degree of the
+/// iteration. The index is `-log_d(G)` scaled by the number of steps.
+pub struct ContinuousPotential;
+
+impl ColoringAlgorithm for ContinuousPotential {
+  fn name(&self) -> &'static str { "Continuous potential" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let degree = iterator_degree(ctx.frac_args.iterator_kind);
+    let z_abs = (point.re * point.re + point.im * point.im).sqrt();
+    let log_potential = z_abs.ln().ln() - (point.n as f64) * degree.ln();
+    ColorOutput::Index(-log_potential / degree.ln() / ctx.frac_args.steps as f64)
+  }
+}
+
+/// Final absolute value of `z`. Escaped points are measured relative to the
+/// iteration bound on a logarithmic scale, others linearly.
+pub struct FinalMagnitude;
+
+impl ColoringAlgorithm for FinalMagnitude {
+  fn name(&self) -> &'static str { "Final magnitude" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let iter_bound = ctx.frac_args.iter_bound;
+    let abs_val = point.re * point.re + point.im * point.im;
+    if point.n < ctx.frac_args.steps {
+      ColorOutput::Index(abs_val.ln() / iter_bound.ln() - 1.)
+    } else {
+      ColorOutput::Index((abs_val / iter_bound).sqrt())
+    }
+  }
+}
+
+/// Distance estimate. Points on the boundary get the end color and fade
+/// towards the start color with their distance measured in pixels.
+pub struct DistanceEstimate;
+
+impl ColoringAlgorithm for DistanceEstimate {
+  fn name(&self) -> &'static str { "Distance estimate" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let field = ctx.frac_args.field;
+    // Width of a single pixel in the complex plane
+    let pixel_width = 2.0 * field.radius / (field.pixel_size as f64);
+    if point.n < ctx.frac_args.steps {
+      ColorOutput::Index(1. / (1. + point.dist / pixel_width))
+    } else {
+      ColorOutput::Index(1.)
+    }
+  }
+}
+
+/// Minimal distance of the orbit to the orbit trap.
+pub struct OrbitTrap;
+
+impl ColoringAlgorithm for OrbitTrap {
+  fn name(&self) -> &'static str { "Orbit trap" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(point.trap_dist / ctx.img_args.trap_width)
+  }
+}
+
+/// Stripe average of `sin(k arg z)` along the orbit.
+pub struct StripeAverage;
+
+impl ColoringAlgorithm for StripeAverage {
+  fn name(&self) -> &'static str { "Stripe average" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let avg = smooth_average(point, point.stripe, point.stripe_prev, ctx.frac_args);
+    ColorOutput::Index(avg)
+  }
+}
+
+/// Triangle inequality average along the orbit.
+pub struct TriangleAverage;
+
+impl ColoringAlgorithm for TriangleAverage {
+  fn name(&self) -> &'static str { "Triangle inequality average" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let avg =
+      smooth_average(point, point.triangle, point.triangle_prev, ctx.frac_args);
+    ColorOutput::Index(avg)
+  }
+}
+
+/// Smooth iteration shaded by the sign of the imaginary part of `z`.
+pub struct BinaryDecomposition;
+
+impl ColoringAlgorithm for BinaryDecomposition {
+  fn name(&self) -> &'static str { "Binary decomposition" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let x = iteration_fraction(point, ctx);
+    shaded(x, if point.im < 0. { 1. } else { 0. }, ctx)
+  }
+}
+
+/// Smooth iteration shaded by the angle sector of `z`.
+pub struct NaryDecomposition;
+
+impl ColoringAlgorithm for NaryDecomposition {
+  fn name(&self) -> &'static str { "N-ary decomposition" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let sectors = ctx.img_args.decomposition.max(1);
+    let x = iteration_fraction(point, ctx);
+    let sector = ((turn(point) * sectors as f64) as usize).min(sectors - 1);
+    shaded(x, sector as f64 / (sectors - 1).max(1) as f64, ctx)
+  }
+}
+
+/// Smooth iteration shaded by continuous field lines along external rays.
+pub struct FieldLines;
+
+impl ColoringAlgorithm for FieldLines {
+  fn name(&self) -> &'static str { "Field lines" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let lines = ctx.img_args.decomposition.max(1) as f64;
+    let x = iteration_fraction(point, ctx);
+    shaded(x, 0.5 - 0.5 * (2. * PI * turn(point) * lines).cos(), ctx)
+  }
+}
+
+/// Smooth iteration shifted along the gradient by the external angle.
+pub struct ExternalAngle;
+
+impl ColoringAlgorithm for ExternalAngle {
+  fn name(&self) -> &'static str { "External angle" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    let strength = ctx.img_args.angle_strength;
+    let x = iteration_fraction(point, ctx);
+    ColorOutput::Index((1. - strength) * x + strength * turn(point))
+  }
+}
+
+//==============================================================================
+// Interior algorithms
+//==============================================================================
+
+/// Paints every point with the end color of the gradient of the slot.
+pub struct Flat;
+
+impl ColoringAlgorithm for Flat {
+  fn name(&self) -> &'static str { "Flat interior" }
+
+  fn color(&self, _point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Color(color::unit_color(ctx.slot_gradient.end_color))
+  }
+}
+
+/// Final angle of `z`.
+pub struct FinalAngle;
+
+impl ColoringAlgorithm for FinalAngle {
+  fn name(&self) -> &'static str { "Final angle" }
+
+  fn color(&self, point : &FracPoint, _ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(turn(point))
+  }
+}
+
+/// Minimal absolute value along the orbit (Bof60).
+pub struct MinimumMagnitude;
+
+impl ColoringAlgorithm for MinimumMagnitude {
+  fn name(&self) -> &'static str { "Minimum magnitude (Bof60)" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(point.min_abs / ctx.frac_args.iter_bound.sqrt())
+  }
+}
+
+/// Step at which the minimal absolute value was reached (Bof61).
+pub struct MinimumStep;
+
+impl ColoringAlgorithm for MinimumStep {
+  fn name(&self) -> &'static str { "Minimum step (Bof61)" }
+
+  fn color(&self, point : &FracPoint, ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(point.min_step as f64 / ctx.frac_args.steps as f64)
+  }
+}
+
+/// Period of the attracting cycle. Points without a found cycle get the end
+/// of the gradient.
+pub struct CyclePeriod;
+
+impl ColoringAlgorithm for CyclePeriod {
+  fn name(&self) -> &'static str { "Cycle period" }
+
+  fn uses_cycles(&self) -> bool { true }
+
+  fn color(&self, point : &FracPoint, _ctx : &ColoringContext) -> ColorOutput {
+    if point.period > 0 {
+      ColorOutput::Index(1. - 1. / point.period as f64)
+    } else {
+      ColorOutput::Index(1.)
+    }
+  }
+}
+
+/// Absolute value of the multiplier of the attracting cycle.
+pub struct CycleMultiplier;
+
+impl ColoringAlgorithm for CycleMultiplier {
+  fn name(&self) -> &'static str { "Cycle multiplier" }
+
+  fn uses_cycles(&self) -> bool { true }
+
+  fn color(&self, point : &FracPoint, _ctx : &ColoringContext) -> ColorOutput {
+    ColorOutput::Index(point.multiplier)
+  }
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn layer_algorithms_cover_both_slots() {
+    for algorithm in EXTERIOR_ALGORITHMS.iter().chain(INTERIOR_ALGORITHMS) {
+      assert!(LAYER_ALGORITHMS.contains(algorithm), "{} is missing", algorithm);
+    }
+    for (i, algorithm) in LAYER_ALGORITHMS.iter().enumerate() {
+      let listed = EXTERIOR_ALGORITHMS.contains(algorithm)
+        || INTERIOR_ALGORITHMS.contains(algorithm);
+      assert!(listed, "{} is no exterior or interior algorithm", algorithm);
+      assert!(!LAYER_ALGORITHMS[..i].contains(algorithm), "{} is listed twice", algorithm);
+    }
+  }
+}
@@ -0,0 +1,107 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rayon::prelude::*;
+
+use fractal::algorithms::{ColorOutput, ColoringContext};
+use fractal::color;
+use fractal::definitions::*;
+
+//==============================================================================
+// Transparency
+//==============================================================================
+
+/// Returns the opacity, between 0 and 1, that the transparency of `img_args`
+/// gives every point of `fractal`, or `None` if the image stays as it is.
+pub fn opacity(fractal : &RawFrac, frac_args : &FracArgs, img_args : &ImageArgs)
+-> Option<Vec<f64>> {
+  let transparency = img_args.transparency;
+  if !transparency.interior && !transparency.exterior && !transparency.fade {
+    return None
+  }
+  let gradient_fun = color::gradient_function_hdr(img_args.gradient.clone());
+
+  let opacity = fractal.par_iter()
+    .map(|point| {
+      let exterior = point.n < frac_args.steps;
+      if (exterior && transparency.exterior) || (!exterior && transparency.interior) {
+        return 0.
+      }
+      if !transparency.fade {
+        return 1.
+      }
+      let ctx = ColoringContext{
+        frac_args : frac_args,
+        img_args : img_args,
+        slot_gradient : &img_args.gradient,
+        gradient : &gradient_fun,
+      };
+      // Algorithms that pick colors themselves fade by the alpha they pick
+      let value = match transparency.fade_algorithm.0.color(point, &ctx) {
+        ColorOutput::Index(x) => x.max(0.).min(1.),
+        ColorOutput::Color(bgra) => bgra[3],
+      };
+      let value = if transparency.invert_fade { 1. - value } else { value };
+      value.powf(transparency.fade_power)
+    })
+    .collect();
+
+  Some(opacity)
+}
+
+/// Multiplies the alpha of every pixel of the BGRA image `colored` by its
+/// `opacity`.
+pub fn apply_opacity(colored : &mut HdrImage, opacity : &[f64]) {
+  colored.par_chunks_mut(4).zip(opacity.par_iter()).for_each(|(pixel, opacity)| {
+    pixel[3] = (pixel[3] as f64 * opacity) as f32;
+  });
+}
+
+/// Applies the transparency of `img_args` to `colored`, the coloring of
+/// `fractal`.
+pub fn make_transparent(
+  colored : &mut HdrImage,
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs) {
+  if let Some(opacity) = opacity(fractal, frac_args, img_args) {
+    apply_opacity(colored, &opacity);
+  }
+}
+
+//==============================================================================
+// Premultiplied Alpha
+//==============================================================================
+//
+// Resampling straight alpha lets the colors of transparent pixels bleed into
+// their neighbours, so images are premultiplied before resizing.
+
+/// Multiplies the color channels of the BGRA image `image` by alpha.
+pub fn premultiply(image : &mut FracImage) {
+  image.par_chunks_mut(4).for_each(|pixel| {
+    let alpha = pixel[3] as u32;
+    for c in 0..3 {
+      pixel[c] = ((pixel[c] as u32 * alpha + 127) / 255) as u8;
+    }
+  });
+}
+
+/// Divides the color channels of the premultiplied BGRA image `image` by
+/// alpha.
+pub fn unpremultiply(image : &mut FracImage) {
+  image.par_chunks_mut(4).for_each(|pixel| {
+    let alpha = pixel[3] as u32;
+    if alpha == 0 {
+      pixel[..3].copy_from_slice(&[0, 0, 0]);
+      return
+    }
+    for c in 0..3 {
+      pixel[c] = ((pixel[c] as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+    }
+  });
+}
+
+/// Returns whether any pixel of the BGRA image `image` is not fully opaque.
+pub fn has_transparency(image : &FracImage) -> bool {
+  image.par_chunks(4).any(|pixel| pixel[3] < 255)
+}
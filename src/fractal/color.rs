@@ -1,8 +1,15 @@
 //==============================================================================
 // Open crates and libraries
 //==============================================================================
+use fractal::algorithms::{Algorithm, ColorOutput, ColoringContext};
+use fractal::alpha;
 use fractal::definitions::*;
+use fractal::layers;
+use fractal::space;
 
+use std::cmp::Ordering;
+
+use rayon::prelude::*;
 use resize::Pixel::RGBA;
 use resize::Type::Lanczos3;
 
@@ -10,64 +17,363 @@ use resize::Type::Lanczos3;
 // Color Function Creation
 //==============================================================================
 
-/// Creates a function that calculates the RGBA color of a fractal point by
-/// using `gradient`. To properly scale the gradient `max_steps` is required.
-pub fn color_with_gradient(
-  gradient : Gradient, 
-  max_steps : usize)
--> impl Fn(usize, f64, f64) -> (u8, u8, u8, u8) {
-  let Gradient{start_color, peaks, end_color, smooth} = gradient;
+/// Maps the gradient index `x` from the interval [0, 1] to the position in
+/// the gradient as described by `mapping`.
+pub fn map_index(mapping : &GradientMapping, x : f64) -> f64 {
+  let GradientMapping{transform, density, repeat, offset, wrap} = *mapping;
+  let density = density.max(f64::MIN_POSITIVE);
+
+  let x = match transform {
+    IndexTransform::Linear => x,
+    IndexTransform::Log => (1. + density * x).ln() / (1. + density).ln(),
+    IndexTransform::Power => x.powf(1. / density),
+  };
+  let x = x * repeat + offset;
+
+  match wrap {
+    GradientWrap::Clamp => x.max(0.).min(1.),
+    GradientWrap::Repeat => x - x.floor(),
+    GradientWrap::Mirror => {
+      let x = x.rem_euclid(2.);
+      if x > 1. { 2. - x } else { x }
+    }
+  }
+}
+
+//==============================================================================
+// Gradient Curves
+//==============================================================================
+
+/// Gradient colors in a blending color space, positioned at `at`.
+type Knot = (f64, [f64; 4]);
+
+/// Replaces the hues of `knots` with hues that change continuously along
+/// `path`, so that curves can pass over the end of the color wheel. Grays take
+/// the hue of the closest colored neighbour.
+fn unwrap_hues(knots : &mut Vec<Knot>, path : HuePath) {
+  let colored : Vec<usize> = (0..knots.len())
+    .filter(|&i| knots[i].1[1] >= 1e-6)
+    .collect();
+  if colored.is_empty() {
+    return
+  }
+  for i in 0..knots.len() {
+    let closest = colored.iter()
+      .min_by_key(|&&j| (j as isize - i as isize).abs())
+      .unwrap();
+    knots[i].1[0] = knots[*closest].1[0];
+  }
+  for i in 1..knots.len() {
+    let previous = knots[i - 1].1[0];
+    knots[i].1[0] = previous + space::hue_difference(path, previous, knots[i].1[0]);
+  }
+}
+
+/// Returns the slope of every channel at every knot for curves based on cubic
+/// Hermite segments.
+fn knot_tangents(curve : GradientCurve, knots : &[Knot]) -> Vec<[f64; 4]> {
+  let n = knots.len();
+  let mut tangents = vec![[0.; 4]; n];
+  let slope = |i : usize, j : usize, ch : usize| {
+    let h = knots[j].0 - knots[i].0;
+    if h > 0. { (knots[j].1[ch] - knots[i].1[ch]) / h } else { 0. }
+  };
+
+  for ch in 0..4 {
+    for i in 0..n {
+      let previous = if i > 0 { i - 1 } else { i };
+      let next = (i + 1).min(n - 1);
+      tangents[i][ch] = match curve {
+        GradientCurve::CatmullRom => slope(previous, next, ch),
+        GradientCurve::MonotoneCubic if i == 0 || i == n - 1 => 
+          slope(previous, next, ch),
+        GradientCurve::MonotoneCubic => {
+          // Fritsch-Butland tangents do not overshoot
+          let (d0, d1) = (slope(previous, i, ch), slope(i, next, ch));
+          let h0 = knots[i].0 - knots[previous].0;
+          let h1 = knots[next].0 - knots[i].0;
+          if d0 * d1 <= 0. {
+            0.
+          } else {
+            3. * (h0 + h1) / ((2. * h1 + h0) / d0 + (h1 + 2. * h0) / d1)
+          }
+        }
+        GradientCurve::Linear | GradientCurve::BSpline => 0.,
+      };
+    }
+  }
+
+  tangents
+}
+
+/// Returns the channels of the curve between knots `i` and `i + 1` at the
+/// fraction `f` of the segment.
+fn curve_point(
+  curve : GradientCurve,
+  knots : &[Knot],
+  tangents : &[[f64; 4]],
+  i : usize,
+  f : f64)
+-> [f64; 4] {
+  let n = knots.len();
+  let mut point = [0.; 4];
+
+  for ch in 0..4 {
+    let y = |j : usize| knots[j].1[ch];
+    point[ch] = match curve {
+      GradientCurve::Linear => y(i) * (1. - f) + y(i + 1) * f,
+      GradientCurve::MonotoneCubic | GradientCurve::CatmullRom => {
+        // Cubic Hermite segment
+        let h = knots[i + 1].0 - knots[i].0;
+        let (f2, f3) = (f * f, f * f * f);
+        (2. * f3 - 3. * f2 + 1.) * y(i)
+          + (f3 - 2. * f2 + f) * h * tangents[i][ch]
+          + (-2. * f3 + 3. * f2) * y(i + 1)
+          + (f3 - f2) * h * tangents[i + 1][ch]
+      }
+      GradientCurve::BSpline => {
+        // Control points beyond the ends are reflected so that the curve
+        // passes through the start and end color
+        let before = if i > 0 { y(i - 1) } else { 2. * y(0) - y(1) };
+        let after = if i + 2 < n { y(i + 2) } else { 2. * y(n - 1) - y(n - 2) };
+        let g = 1. - f;
+        (g * g * g * before
+          + (3. * f * f * f - 6. * f * f + 4.) * y(i)
+          + (-3. * f * f * f + 3. * f * f + 3. * f + 1.) * y(i + 1)
+          + f * f * f * after) / 6.
+      }
+    };
+  }
+
+  point
+}
+
+//==============================================================================
+// Gradient Functions
+//==============================================================================
+
+/// Creates a function that maps `x` from the interval [0, 1] to the RGBA color
+/// that `gradient` has at `x`, after `x` is mapped by the gradient mapping.
+/// Colors are blended in the color space of the gradient along its curve.
+pub fn gradient_function(gradient : Gradient)
+-> impl Fn(f64) -> (u8, u8, u8, u8) {
+  let mapping = gradient.mapping;
+  let blend_fun = blend_function(gradient);
+
+  move |x| blend_fun(map_index(&mapping, x))
+}
+
+/// Like `gradient_function`, but the color channels are on the scale [0, 1]
+/// and not rounded to 8 bits. Used by the high precision coloring.
+pub fn gradient_function_hdr(gradient : Gradient)
+-> impl Fn(f64) -> [f64; 4] {
+  let mapping = gradient.mapping;
+  let (space, curve_fun) = blend_curve(gradient);
+
+  move |x| space::from_space_unit(space, curve_fun(map_index(&mapping, x)))
+}
+
+/// Creates a function that maps the gradient position `x` from the interval
+/// [0, 1] to the RGBA color of `gradient` at `x`, ignoring the mapping.
+fn blend_function(gradient : Gradient)
+-> impl Fn(f64) -> (u8, u8, u8, u8) {
+  let (space, curve_fun) = blend_curve(gradient);
+
+  move |x| space::from_space(space, curve_fun(x))
+}
+
+/// Converts the 8 bit BGRA color `bgra` to channels on the scale [0, 1].
+pub fn unit_color(bgra : (u8, u8, u8, u8)) -> [f64; 4] {
+  let (b, g, r, a) = bgra;
+  [b as f64 / 255., g as f64 / 255., r as f64 / 255., a as f64 / 255.]
+}
+
+/// Creates a function that maps the gradient position `x` from the interval
+/// [0, 1] to the color of `gradient` at `x` in its blending color space,
+/// which is returned as well.
+fn blend_curve(gradient : Gradient)
+-> (ColorSpace, impl Fn(f64) -> [f64; 4]) {
+  let Gradient{start_color, peaks, end_color, blend, ..} = gradient;
+  let GradientBlend{space, hue_path, curve} = blend;
 
   // Sort peaks by `at`
   let mut peaks = peaks.to_vec();
   peaks.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
 
+  // All gradient colors in the blending color space, including the start and
+  // end color
+  let mut knots = vec![(0., space::to_space(space, start_color))];
+  for peak in peaks.iter() {
+    knots.push((peak.at, space::to_space(space, peak.bgra)));
+  }
+  knots.push((1., space::to_space(space, end_color)));
+
+  let hue_curve = curve != GradientCurve::Linear && space::has_hue(space);
+  if hue_curve {
+    unwrap_hues(&mut knots, hue_path);
+  }
+  let tangents = knot_tangents(curve, &knots);
+
   // Definition of gradient
-  move |n, re, im|{
-
-    // Apply smoothing (only works for points which crossed iter_bound)
-    let n =
-      if smooth && n < max_steps{
-        let abs_val = re * re + im * im;
-        let smooth1 = abs_val.ln() / f64::ln(2.);
-        let smooth2 = smooth1.ln() / f64::ln(2.);
-        (n as f64) - smooth2
-      } else {
-        n as f64
-      };
+  let curve_fun = move |x|{
+    // Get the two bounding knots
+    let i = knots.iter().rposition(|knot| knot.0 <= x).unwrap_or(0);
+    let i = i.min(knots.len() - 2);
+    let (at1, color1) = knots[i];
+    let (at2, color2) = knots[i + 1];
 
-    // Transfer `n` to interval [0, 1]
-    let x = (n / (max_steps as f64)).max(0.0).min(1.0);
+    // Combine colors
+    let f = if at2 > at1 { ((x - at1) / (at2 - at1)).max(0.).min(1.) } else { 1. };
+    if curve == GradientCurve::Linear {
+      space::mix(space, hue_path, color1, color2, f)
+    } else {
+      let mut color = curve_point(curve, &knots, &tangents, i, f);
+      if hue_curve {
+        color[0] = color[0].rem_euclid(1.);
+      }
+      color
+    }
+  };
+  (space, curve_fun)
+}
 
-    // Get the two bounding peaks
-    let mut peak1 = ColorPeak{at: 0., bgra: start_color};
-    let mut peak2 = ColorPeak{at: 0., bgra: start_color};
-    let mut i = 0;
-    while i < peaks.len() && peak2.at <= x {
-      peak1 = peak2;
-      peak2 = peaks[i];
-      i += 1;
+/// Number of colors sampled into a gradient lookup table.
+const TABLE_SIZE : usize = 4096;
+
+/// Colors of a gradient sampled at `TABLE_SIZE` evenly spread positions, with
+/// channels on the scale [0, 1]. Looks up colors much faster than
+/// `gradient_function_hdr` at the cost of rounding the position to the
+/// nearest sample.
+pub struct GradientTable {
+  mapping : GradientMapping,
+  colors : Vec<[f64; 4]>,
+}
+
+impl GradientTable {
+
+  /// Samples the colors of `gradient`.
+  pub fn new(gradient : &Gradient) -> GradientTable {
+    let (space, curve_fun) = blend_curve(gradient.clone());
+    let last = (TABLE_SIZE - 1) as f64;
+
+    GradientTable {
+      mapping : gradient.mapping,
+      colors : (0..TABLE_SIZE)
+        .map(|i| space::from_space_unit(space, curve_fun(i as f64 / last)))
+        .collect(),
     }
-    if i == peaks.len() && peak2.at <= x {
-      peak1 = peak2;
-      peak2 = ColorPeak{at: 1.0, bgra: end_color};
+  }
+
+  /// Returns the color the gradient has at `x`, after `x` is mapped by the
+  /// gradient mapping.
+  pub fn color(&self, x : f64) -> [f64; 4] {
+    // Mapped positions are never negative, so adding a half rounds
+    let x = map_index(&self.mapping, x);
+    let i = (x * (TABLE_SIZE - 1) as f64 + 0.5) as usize;
+    self.colors[i.min(TABLE_SIZE - 1)]
+  }
+}
+
+//==============================================================================
+// Histogram Equalization
+//==============================================================================
+
+/// Distribution of the exterior gradient indices of a fractal. Used to spread
+/// the indices evenly over the gradient.
+pub struct Histogram {
+  sorted : Vec<f64>,
+}
+
+impl Histogram {
+
+  /// Collects the exterior gradient indices of `fractal` colored as specified
+  /// by `img_args`. The fraction `img_args.equalize_clip` of the lowest and of
+  /// the highest indices is dropped so that outliers cannot dominate.
+  pub fn new(fractal : &RawFrac, frac_args : &FracArgs, img_args : &ImageArgs)
+  -> Histogram {
+    let gradient_fun = gradient_function_hdr(img_args.gradient.clone());
+    let ctx = ColoringContext{
+      frac_args : frac_args, 
+      img_args : img_args, 
+      slot_gradient : &img_args.gradient,
+      gradient : &gradient_fun,
+    };
+
+    let mut sorted : Vec<f64> = fractal.iter()
+      .filter(|point| point.n < frac_args.steps)
+      .filter_map(|point| match img_args.exterior.0.color(point, &ctx) {
+        ColorOutput::Index(x) if !x.is_nan() => Some(x),
+        _ => None,
+      })
+      .collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    // Drop outliers on both ends
+    let clip = img_args.equalize_clip.max(0.).min(0.5);
+    let cut = (clip * sorted.len() as f64) as usize;
+    let kept = sorted[cut..sorted.len() - cut].to_vec();
+
+    Histogram{sorted : kept}
+  }
+
+  /// Maps the index `x` to the fraction of collected indices below it. Indices
+  /// outside of the clipped range are mapped to 0 or 1.
+  pub fn equalize(&self, x : f64) -> f64 {
+    if self.sorted.is_empty() {
+      return x
     }
+    let below = match self.sorted.binary_search_by(|v| {
+      if *v < x { Ordering::Less } else { Ordering::Greater }
+    }) {
+      Ok(i) | Err(i) => i,
+    };
+    below as f64 / self.sorted.len() as f64
+  }
+}
 
-    // Extract peak colors at f64
-    let (b1, g1, r1, a1) = peak1.bgra;
-    let (b1, g1, r1, a1) = (b1 as f64, g1 as f64, r1 as f64, a1 as f64);
-    let (b2, g2, r2, a2) = peak2.bgra;
-    let (b2, g2, r2, a2) = (b2 as f64, g2 as f64, r2 as f64, a2 as f64);
-    // Combine colors
-    let f = (x - peak1.at) / (peak2.at - peak1.at);
+//==============================================================================
+// Point Coloring
+//==============================================================================
 
-    let b = ((b1 * (1. - f) + b2 * f).round() as isize).min(255).max(0);
-    let g = ((g1 * (1. - f) + g2 * f).round() as isize).min(255).max(0);
-    let r = ((r1 * (1. - f) + r2 * f).round() as isize).min(255).max(0);
-    let a = ((a1 * (1. - f) + a2 * f).round() as isize).min(255).max(0);
+/// Creates a function that calculates the BGRA color of a fractal point, with
+/// channels on the scale [0, 1], by using the coloring algorithms and
+/// gradients of `img_args`. Escaped points use the exterior algorithm and
+/// gradient, the rest the interior ones. To properly scale the gradient the
+/// fractal arguments `frac_args` are required. If `histogram` is given,
+/// exterior indices are equalized with it.
+pub fn color_with_gradient<'a>(
+  img_args : &'a ImageArgs, 
+  frac_args : &'a FracArgs,
+  histogram : Option<Histogram>)
+-> impl Fn(&FracPoint) -> [f64; 4] + 'a {
+  let exterior_fun = gradient_function_hdr(img_args.gradient.clone());
+  let interior_fun = gradient_function_hdr(img_args.interior_gradient.clone());
 
-    // println!("n:{} x:{} i:{} f:{} bgra:{},{},{},{}", n, x, i, f, b, g, r, a);
-    (b as u8, g as u8, r as u8, a as u8)
+  move |point|{
+    let exterior = point.n < frac_args.steps;
+    let (algorithm, gradient, gradient_fun) : (Algorithm, _, &dyn Fn(f64) -> _) =
+      if exterior {
+        (img_args.exterior, &img_args.gradient, &exterior_fun)
+      } else {
+        (img_args.interior, &img_args.interior_gradient, &interior_fun)
+      };
+    let ctx = ColoringContext{
+      frac_args : frac_args, 
+      img_args : img_args, 
+      slot_gradient : gradient,
+      gradient : gradient_fun,
+    };
+
+    match algorithm.0.color(point, &ctx) {
+      ColorOutput::Index(x) => {
+        let x = match histogram {
+          Some(ref histogram) if exterior => histogram.equalize(x),
+          _ => x,
+        };
+        gradient_fun(x.max(0.0).min(1.0))
+      }
+      ColorOutput::Color(bgra) => bgra,
+    }
   }
 
 }
@@ -76,46 +382,62 @@ pub fn color_with_gradient(
 // Fractal Coloring
 //==============================================================================
 
-/// Takes fractal data `fractal` and colors it with the color `color`. To
-/// correctly render the gradient it is required to know `max_steps`.
+/// Takes fractal data `fractal` and colors it as specified by image arguments
+/// `img_args`, including its layers. To correctly render the gradient it is
+/// required to know the fractal arguments `frac_args` the fractal was
+/// computed with. The channels are kept on the scale [0, 1] instead of being
+/// rounded to 8 bits, so smooth gradients do not band.
 pub fn color_fractal(
   fractal : &RawFrac, 
-  max_steps : usize, 
-  gradient : Gradient ) 
--> FracImage {
+  frac_args : &FracArgs, 
+  img_args : &ImageArgs ) 
+-> HdrImage {
   // initialize vector with enough fields (4 times for BGRA format)
-  let mut colored = Vec::with_capacity(4 * fractal.len());
-
-  // create coloring function
-  let color_fun = color_with_gradient(gradient, max_steps);
-
-  // make closure for coloring a single data point
-  let add_colored_pixel = |vec : &mut FracImage, data : FracPoint| {
-    let (n, re, im) = data;
-    let (b, g, r, a) = color_fun(n, re, im);
-    vec.push(b);
-    vec.push(g);
-    vec.push(r);
-    vec.push(a);
-  };
+  let mut colored = vec![0.; 4 * fractal.len()];
+
+  // create coloring function, equalized over the whole fractal if required
+  let histogram = 
+    if img_args.equalize {
+      Some(Histogram::new(fractal, frac_args, img_args))
+    } else {
+      None
+    };
+  let color_fun = color_with_gradient(img_args, frac_args, histogram);
 
   // color entire fractal
-  fractal.iter().for_each(|&data| add_colored_pixel(&mut colored, data));
+  colored.par_chunks_mut(4).zip(fractal.par_iter()).for_each(|(pixel, point)| {
+    let bgra = color_fun(point);
+    for c in 0..4 {
+      pixel[c] = bgra[c] as f32;
+    }
+  });
+  layers::composite_layers(&mut colored, fractal, frac_args, img_args);
 
   colored
 }
 
 /// Resizes fractal `image` from the dimensions `from` to dimensions `to` by
-/// using a resizer. Greatly improves quality of image preview.
+/// using a resizer. Greatly improves quality of image preview. Images with
+/// transparency are resampled with premultiplied alpha.
 pub fn resize_fractal_image(
   image : &FracImage, 
   from : usize, 
   to : usize) 
 -> FracImage {
+  if from == to {
+    return image.clone()
+  }
   let mut resized = vec![0;to*to*4];
   let mut resizer = resize::new(from, from, to, to, RGBA, Lanczos3);
 
-  resizer.resize(image, &mut resized);
+  if alpha::has_transparency(image) {
+    let mut premultiplied = image.clone();
+    alpha::premultiply(&mut premultiplied);
+    resizer.resize(&premultiplied, &mut resized);
+    alpha::unpremultiply(&mut resized);
+  } else {
+    resizer.resize(image, &mut resized);
+  }
 
   resized
 }
@@ -138,3 +460,161 @@ pub fn bgra_to_rgba(image_vec : &Vec<u8>) -> Vec<u8> {
   bitflipped
 }
 
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use fractal::draw;
+
+  /// The color of `gradient` at `x` as found by walking the peaks and
+  /// interpolating the bytes, the way gradients were colored before they got
+  /// blend spaces and curves.
+  fn byte_walk(gradient : &Gradient, x : f64) -> (u8, u8, u8, u8) {
+    let mut peaks = gradient.peaks.clone();
+    peaks.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
+
+    let mut peak1 = ColorPeak{at : 0., bgra : gradient.start_color};
+    let mut peak2 = ColorPeak{at : 0., bgra : gradient.start_color};
+    let mut i = 0;
+    while i < peaks.len() && peak2.at <= x {
+      peak1 = peak2;
+      peak2 = peaks[i];
+      i += 1;
+    }
+    if i == peaks.len() && peak2.at <= x {
+      peak1 = peak2;
+      peak2 = ColorPeak{at : 1.0, bgra : gradient.end_color};
+    }
+
+    let f = (x - peak1.at) / (peak2.at - peak1.at);
+    let mix = |c1 : u8, c2 : u8| {
+      ((c1 as f64 * (1. - f) + c2 as f64 * f).round() as isize).min(255).max(0) as u8
+    };
+    let ((b1, g1, r1, a1), (b2, g2, r2, a2)) = (peak1.bgra, peak2.bgra);
+    (mix(b1, b2), mix(g1, g2), mix(r1, r2), mix(a1, a2))
+  }
+
+  /// Mapping with the given wrap, repetition and offset and no transform.
+  fn wrapping(wrap : GradientWrap, repeat : f64, offset : f64) -> GradientMapping {
+    let default = GradientMapping::default();
+    GradientMapping{wrap : wrap, repeat : repeat, offset : offset, ..default}
+  }
+
+  fn assert_close(found : f64, expected : f64) {
+    assert!((found - expected).abs() < 1e-12, "found {}, expected {}", found, expected);
+  }
+
+  #[test]
+  fn clamp_holds_the_end_colors() {
+    let mapping = wrapping(GradientWrap::Clamp, 2., 0.25);
+    assert_close(map_index(&mapping, 0.), 0.25);
+    assert_close(map_index(&mapping, 0.25), 0.75);
+    assert_close(map_index(&mapping, 0.5), 1.);
+    assert_close(map_index(&mapping, 1.), 1.);
+    let mapping = wrapping(GradientWrap::Clamp, 1., -0.5);
+    assert_close(map_index(&mapping, 0.2), 0.);
+  }
+
+  #[test]
+  fn repeat_starts_over() {
+    let mapping = wrapping(GradientWrap::Repeat, 3., 0.1);
+    assert_close(map_index(&mapping, 0.), 0.1);
+    assert_close(map_index(&mapping, 0.5), 0.6);
+    assert_close(map_index(&mapping, 0.9), 0.8);
+    let mapping = wrapping(GradientWrap::Repeat, 1., -0.25);
+    assert_close(map_index(&mapping, 0.), 0.75);
+  }
+
+  #[test]
+  fn mirror_runs_back_and_forth() {
+    let mapping = wrapping(GradientWrap::Mirror, 3., 0.);
+    assert_close(map_index(&mapping, 0.), 0.);
+    assert_close(map_index(&mapping, 0.25), 0.75);
+    assert_close(map_index(&mapping, 0.5), 0.5);
+    assert_close(map_index(&mapping, 1.), 1.);
+    let mapping = wrapping(GradientWrap::Mirror, 1., -0.25);
+    assert_close(map_index(&mapping, 0.), 0.25);
+  }
+
+  #[test]
+  fn transforms_keep_the_ends_and_spread_by_density() {
+    let transformed = |transform, density| {
+      GradientMapping{transform : transform, density : density, ..GradientMapping::default()}
+    };
+    for &transform in &[IndexTransform::Linear, IndexTransform::Log, IndexTransform::Power] {
+      assert_close(map_index(&transformed(transform, 4.), 0.), 0.);
+      assert_close(map_index(&transformed(transform, 4.), 1.), 1.);
+    }
+    let log = transformed(IndexTransform::Log, 4.);
+    assert_close(map_index(&log, 0.5), 3f64.ln() / 5f64.ln());
+    let power = transformed(IndexTransform::Power, 4.);
+    assert_close(map_index(&power, 0.0625), 0.5);
+    // A density of zero must not divide by zero
+    assert!(!map_index(&transformed(IndexTransform::Power, 0.), 0.5).is_nan());
+  }
+
+  /// A small fractal with exterior points of many different iteration counts.
+  fn fractal() -> (FracArgs, RawFrac) {
+    let mut args = FracArgs::default();
+    args.field.pixel_size = 32;
+    args.c_re = -0.8;
+    args.c_im = 0.156;
+    (args, draw::compute_fractal(args))
+  }
+
+  #[test]
+  fn equalized_indices_rise_with_the_index() {
+    let (frac_args, fractal) = fractal();
+    let histogram = Histogram::new(&fractal, &frac_args, &ImageArgs::default());
+    let mut last = 0.;
+    for k in 0..1001 {
+      let equalized = histogram.equalize(k as f64 / 1000.);
+      assert!(equalized >= last && equalized <= 1., "{} after {}", equalized, last);
+      last = equalized;
+    }
+    assert_eq!(histogram.equalize(-1.), 0.);
+    assert_eq!(histogram.equalize(2.), 1.);
+  }
+
+  #[test]
+  fn equalize_clip_is_bounded() {
+    let (frac_args, fractal) = fractal();
+    let mut img_args = ImageArgs::default();
+    img_args.equalize_clip = 0.;
+    let collected = Histogram::new(&fractal, &frac_args, &img_args).sorted.len();
+    assert!(collected > 100);
+
+    img_args.equalize_clip = -0.2;
+    assert_eq!(Histogram::new(&fractal, &frac_args, &img_args).sorted.len(), collected);
+    img_args.equalize_clip = 0.1;
+    let cut = (0.1 * collected as f64) as usize;
+    let kept = Histogram::new(&fractal, &frac_args, &img_args).sorted.len();
+    assert_eq!(kept, collected - 2 * cut);
+    // Clipping half on both ends leaves nothing, so indices stay as they are
+    for &clip in &[0.5, 0.8] {
+      img_args.equalize_clip = clip;
+      let histogram = Histogram::new(&fractal, &frac_args, &img_args);
+      assert!(histogram.sorted.len() <= 1);
+      if histogram.sorted.is_empty() {
+        assert_eq!(histogram.equalize(0.3), 0.3);
+      }
+    }
+  }
+
+  #[test]
+  fn srgb_presets_match_the_byte_walk() {
+    let presets = [Gradient::azul(), Gradient::svarog(), Gradient::emperor(), Gradient::gaia()];
+    for preset in presets.iter() {
+      assert_eq!(preset.blend.space, ColorSpace::Srgb);
+      let gradient_fun = gradient_function(preset.clone());
+      for k in 0..1001 {
+        let x = k as f64 / 1000.;
+        assert_eq!(gradient_fun(x), byte_walk(preset, x), "at {}", x);
+      }
+    }
+  }
+}
@@ -0,0 +1,176 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rayon::prelude::*;
+
+use fractal::algorithms::{ColorOutput, ColoringContext};
+use fractal::alpha;
+use fractal::color;
+use fractal::color::{GradientTable, Histogram};
+use fractal::definitions::*;
+use fractal::layers;
+use fractal::lighting;
+use fractal::post;
+use fractal::texture;
+use fractal::texture::TextureHits;
+
+//==============================================================================
+// Palette Cycling
+//==============================================================================
+
+/// Everything about a colored fractal that does not change when only the
+/// gradients change, so that palette cycling can recolor a fractal every
+/// frame.
+///
+/// The gradient index of every point is computed once (after histogram
+/// equalization), points that their coloring algorithm colors directly are
+/// recolored each frame. Layers, texture hits, lighting and transparency are
+/// computed once as well, only the base coloring cycles. Post-processing and
+/// the rounding to 8 bits run every frame.
+pub struct PaletteCycle {
+  indices : Vec<Option<f64>>,
+  layers : Vec<HdrImage>,
+  hits : Option<Vec<(f64, f64, f64, f64)>>,
+  shading : Option<Vec<Option<(f64, f64)>>>,
+  opacity : Option<Vec<f64>>,
+}
+
+impl PaletteCycle {
+
+  /// Prepares `fractal` computed with `frac_args` for cycling the gradients
+  /// of `img_args`. Texture hits are taken from `hits` if they fit and stored
+  /// there otherwise.
+  pub fn new(
+    fractal : &RawFrac,
+    frac_args : &FracArgs,
+    img_args : &ImageArgs,
+    hits : &mut Option<TextureHits>)
+  -> PaletteCycle {
+    let histogram =
+      if img_args.equalize {
+        Some(Histogram::new(fractal, frac_args, img_args))
+      } else {
+        None
+      };
+    // The gradient colors are never used for points colored by their index
+    let gradient_fun = color::gradient_function_hdr(img_args.gradient.clone());
+    let exterior_ctx = ColoringContext{
+      frac_args : frac_args,
+      img_args : img_args,
+      slot_gradient : &img_args.gradient,
+      gradient : &gradient_fun,
+    };
+    let interior_ctx = ColoringContext{
+      slot_gradient : &img_args.interior_gradient,
+      ..exterior_ctx
+    };
+
+    let indices = fractal.iter()
+      .map(|point| {
+        let exterior = point.n < frac_args.steps;
+        let (algorithm, ctx) =
+          if exterior {
+            (img_args.exterior, &exterior_ctx)
+          } else {
+            (img_args.interior, &interior_ctx)
+          };
+        match algorithm.0.color(point, ctx) {
+          ColorOutput::Index(x) => {
+            let x = match histogram {
+              Some(ref histogram) if exterior => histogram.equalize(x),
+              _ => x,
+            };
+            Some(x.max(0.).min(1.))
+          }
+          ColorOutput::Color(_) => None,
+        }
+      })
+      .collect();
+
+    let hits = texture::cached_hits(hits, frac_args, img_args)
+      .map(|hits| hits.hits.clone());
+
+    let shading = match img_args.lighting.model {
+      LightingModel::Off => None,
+      _ => Some(lighting::shading(fractal, frac_args, &img_args.lighting)),
+    };
+
+    let layers = img_args.layers.iter()
+      .map(|layer| layers::color_layer(fractal, frac_args, img_args, layer))
+      .collect();
+
+    let opacity = alpha::opacity(fractal, frac_args, img_args);
+
+    PaletteCycle{
+      indices : indices,
+      layers : layers,
+      hits : hits,
+      shading : shading,
+      opacity : opacity,
+    }
+  }
+
+  /// Colors `fractal` with the gradients of `img_args`, which may differ from
+  /// the ones the cycle was prepared with only in their colors and mapping.
+  /// Colors are looked up in gradient tables.
+  pub fn color(&self, fractal : &RawFrac, frac_args : &FracArgs, img_args : &ImageArgs)
+  -> FracImage {
+    let exterior_table = GradientTable::new(&img_args.gradient);
+    let interior_table = GradientTable::new(&img_args.interior_gradient);
+    let mut colored = vec![0.; 4 * fractal.len()];
+
+    let color_point = |point : &FracPoint, index : Option<f64>| {
+      let exterior = point.n < frac_args.steps;
+      let table = if exterior { &exterior_table } else { &interior_table };
+      match index {
+        Some(x) => table.color(x),
+        None => {
+          let (algorithm, gradient) =
+            if exterior {
+              (img_args.exterior, &img_args.gradient)
+            } else {
+              (img_args.interior, &img_args.interior_gradient)
+            };
+          let gradient_fun = |x| table.color(x);
+          let ctx = ColoringContext{
+            frac_args : frac_args,
+            img_args : img_args,
+            slot_gradient : gradient,
+            gradient : &gradient_fun,
+          };
+          match algorithm.0.color(point, &ctx) {
+            ColorOutput::Index(x) => table.color(x.max(0.).min(1.)),
+            ColorOutput::Color(bgra) => bgra,
+          }
+        }
+      }
+    };
+
+    // Color whole rows in parallel, single pixels are too little work
+    let width = 4 * frac_args.field.pixel_size;
+    colored.par_chunks_mut(width).enumerate().for_each(|(row_num, row)| {
+      let start = row_num * frac_args.field.pixel_size;
+      for (k, pixel) in row.chunks_mut(4).enumerate() {
+        let bgra = color_point(&fractal[start + k], self.indices[start + k]);
+        for c in 0..4 {
+          pixel[c] = bgra[c] as f32;
+        }
+      }
+    });
+
+    for (layer, layer_image) in img_args.layers.iter().zip(self.layers.iter()) {
+      layers::composite(&mut colored, layer_image, layer);
+    }
+    if let Some(ref hits) = self.hits {
+      texture::blend_hits(&mut colored, hits);
+    }
+    if let Some(ref shading) = self.shading {
+      lighting::apply_shading(&mut colored, shading);
+    }
+    post::process(&mut colored, frac_args.field.pixel_size, &img_args.post);
+    if let Some(ref opacity) = self.opacity {
+      alpha::apply_opacity(&mut colored, opacity);
+    }
+    post::quantize(&colored, frac_args.field.pixel_size, img_args.post.dither)
+  }
+}
@@ -1,3 +1,9 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use fractal::algorithms;
+use fractal::algorithms::Algorithm;
+
 //==============================================================================
 // Field
 //==============================================================================
@@ -9,7 +15,7 @@
 ///
 /// `pixel_size` states the number of pixels per square side, which defines the
 /// `pixel_size * pixel_size` point mesh for evaluation.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Field { 
   pub pixel_size  : usize,
   pub center_re   : f64, 
@@ -32,10 +38,41 @@ impl Default for Field {
 // Custom types and enums
 //==============================================================================
 
-/// Resulting type of fractal drawing. The `usize` lists the number of
-/// steps taken before the iteration terminated. The `f64` elements describe 
-/// the complex value at the end of iteration (required for smoothing).
-pub type FracPoint = (usize, f64, f64);
+/// Resulting type of fractal drawing. Describes a single point of the
+/// calculated fractal.
+/// - `n` number of steps taken before the iteration terminated
+/// - `(re, im)` the complex value at the end of iteration (required for
+///   smoothing)
+/// - `dist` exterior distance estimate from the point to the fractal in
+///   complex units, `0.` for points that did not escape
+/// - `trap_dist` minimal distance of the orbit to the orbit trap
+/// - `trap_step` the step at which `trap_dist` was reached
+/// - `stripe` stripe average along the orbit and `stripe_prev` the same
+///   average without the last step (required for smoothing)
+/// - `triangle` triangle inequality average along the orbit and
+///   `triangle_prev` the same average without the last step
+/// - `min_abs` minimal absolute value along the orbit and `min_step` the step
+///   at which it was reached
+/// - `period` period of the attracting cycle the orbit of a non-escaping
+///   point falls into (`0` if none was found) and `multiplier` the absolute
+///   value of the cycle multiplier
+#[derive (Clone, Copy, Debug, Default)]
+pub struct FracPoint {
+  pub n    : usize,
+  pub re   : f64,
+  pub im   : f64,
+  pub dist : f64,
+  pub trap_dist : f64,
+  pub trap_step : usize,
+  pub stripe        : f64,
+  pub stripe_prev   : f64,
+  pub triangle      : f64,
+  pub triangle_prev : f64,
+  pub min_abs    : f64,
+  pub min_step   : usize,
+  pub period     : usize,
+  pub multiplier : f64,
+}
 
 /// Type of a calculated fractal.
 pub type RawFrac = Vec<FracPoint>;
@@ -44,6 +81,11 @@ pub type RawFrac = Vec<FracPoint>;
 /// `u8` values.
 pub type FracImage = Vec<u8>;
 
+/// Type of a colored fractal image in high precision. A flattened vector of
+/// BGRA pixels with `f32` values on the scale [0, 1], lighting highlights may
+/// exceed 1.
+pub type HdrImage = Vec<f32>;
+
 /// States whether the fractal calculation uses the Julia or Mandelbrot style
 /// of point iteration.
 #[derive (Clone, Copy, Debug, PartialEq, Eq)]
@@ -85,6 +127,95 @@ pub static ALL_ITERATORS : &[IteratorKind] = &[
   IteratorKind::Ship,
   ];
 
+/// Describes the shape of the orbit trap. The distance of an orbit point to
+/// the trap is measured to:
+/// - `Point` the center of the trap
+/// - `Line` the line through the center in the direction of `angle`
+/// - `Cross` two perpendicular lines through the center, rotated by `angle`
+/// - `Circle` the circle around the center with the trap `radius`
+/// - `Stalks` the real and imaginary axis moved to the center (Pickover)
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapShape { Point, Line, Cross, Circle, Stalks }
+
+impl std::fmt::Display for TrapShape {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     TrapShape::Point => "Point trap", 
+     TrapShape::Line => "Line trap", 
+     TrapShape::Cross => "Cross trap", 
+     TrapShape::Circle => "Circle trap", 
+     TrapShape::Stalks => "Pickover stalks", 
+    })
+  }
+}
+
+pub static ALL_TRAP_SHAPES : &[TrapShape] = &[
+  TrapShape::Point,
+  TrapShape::Line,
+  TrapShape::Cross,
+  TrapShape::Circle,
+  TrapShape::Stalks,
+  ];
+
+/// Describes the orbit trap tracked during iteration. The trap is centered in
+/// `(re, im)`, `radius` is used by circle traps and `angle` (in radians) by
+/// line and cross traps.
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct OrbitTrap {
+  pub shape  : TrapShape,
+  pub re     : f64,
+  pub im     : f64,
+  pub radius : f64,
+  pub angle  : f64,
+}
+
+impl Default for OrbitTrap {
+  fn default() -> Self {
+    OrbitTrap {
+      shape  : TrapShape::Point,
+      re     : 0.,
+      im     : 0.,
+      radius : 1.,
+      angle  : 0.,
+    }
+  }
+}
+
+/// Decides how the colors of an image texture are combined when the orbit of
+/// a point passes through the texture several times.
+/// - `First` the color of the first hit is used
+/// - `Last` the color of the last hit is used
+/// - `Composite` all hits are alpha composited with earlier hits on top
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureHit { First, Last, Composite }
+
+impl std::fmt::Display for TextureHit {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     TextureHit::First => "First hit", 
+     TextureHit::Last => "Last hit", 
+     TextureHit::Composite => "Composite hits", 
+    })
+  }
+}
+
+pub static ALL_TEXTURE_HITS : &[TextureHit] = &[
+  TextureHit::First,
+  TextureHit::Last,
+  TextureHit::Composite,
+  ];
+
+/// An image used as an orbit trap. The `width * height` image is stored as a
+/// flattened vector of BGRA pixels in `pixels`. In the complex plane the
+/// texture is centered in the orbit trap and is as wide as its diameter, its
+/// height follows from the aspect ratio of the image.
+#[derive (Clone, Debug, PartialEq)]
+pub struct TextureTrap {
+  pub pixels : Vec<u8>,
+  pub width  : usize,
+  pub height : usize,
+}
+
 // Used to describe gradients. `bgra` specifies the color in BGRA `u8` format
 // and `at` is a float (expected between 0 and 1) specifying where the gradient
 // reaches the chosen color if one colors the interval [0, 1].
@@ -94,22 +225,496 @@ pub struct ColorPeak {
   pub at : f64
 }
 
+/// Describes what happens with gradient indices that fall outside [0, 1] after
+/// repetition and offset. `Clamp` holds the end colors, `Repeat` starts the
+/// gradient over and `Mirror` runs it back and forth.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradientWrap { Clamp, Repeat, Mirror }
+
+impl std::fmt::Display for GradientWrap {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     GradientWrap::Clamp => "Clamp", 
+     GradientWrap::Repeat => "Repeat", 
+     GradientWrap::Mirror => "Mirror", 
+    })
+  }
+}
+
+pub static ALL_GRADIENT_WRAPS : &[GradientWrap] = &[
+  GradientWrap::Clamp,
+  GradientWrap::Repeat,
+  GradientWrap::Mirror,
+  ];
+
+/// Describes the transformation applied to gradient indices before they are
+/// repeated. With the density `d` the index `x` is mapped to:
+/// - `Linear` `x`
+/// - `Log` `ln(1 + d x) / ln(1 + d)`
+/// - `Power` `x ^ (1 / d)`
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexTransform { Linear, Log, Power }
+
+impl std::fmt::Display for IndexTransform {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     IndexTransform::Linear => "Linear index", 
+     IndexTransform::Log => "Logarithmic index", 
+     IndexTransform::Power => "Power index", 
+    })
+  }
+}
+
+pub static ALL_INDEX_TRANSFORMS : &[IndexTransform] = &[
+  IndexTransform::Linear,
+  IndexTransform::Log,
+  IndexTransform::Power,
+  ];
+
+/// Describes how gradient indices are mapped onto the gradient.
+/// - `transform` and `density` transform the index first
+/// - `repeat` number of times the gradient is repeated over [0, 1] (the
+///   period in iterations is `steps / repeat` for iteration colorings)
+/// - `offset` phase by which the gradient is shifted
+/// - `wrap` handles indices outside of [0, 1]
+#[derive (Clone, Copy, Debug)]
+pub struct GradientMapping {
+  pub transform : IndexTransform,
+  pub density : f64,
+  pub repeat : f64,
+  pub offset : f64,
+  pub wrap : GradientWrap,
+}
+
+impl Default for GradientMapping {
+  fn default() -> Self {
+    GradientMapping {
+      transform : IndexTransform::Linear,
+      density : 1.,
+      repeat : 1.,
+      offset : 0.,
+      wrap : GradientWrap::Clamp,
+    }
+  }
+}
+
+/// Describes the color space in which gradient colors are blended. `Srgb`
+/// blends the stored BGRA values directly.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace { Srgb, LinearRgb, Oklab, Oklch, Hsv, Hsl }
+
+impl std::fmt::Display for ColorSpace {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     ColorSpace::Srgb => "sRGB", 
+     ColorSpace::LinearRgb => "Linear RGB", 
+     ColorSpace::Oklab => "Oklab", 
+     ColorSpace::Oklch => "OkLCh", 
+     ColorSpace::Hsv => "HSV", 
+     ColorSpace::Hsl => "HSL", 
+    })
+  }
+}
+
+pub static ALL_COLOR_SPACES : &[ColorSpace] = &[
+  ColorSpace::Srgb,
+  ColorSpace::LinearRgb,
+  ColorSpace::Oklab,
+  ColorSpace::Oklch,
+  ColorSpace::Hsv,
+  ColorSpace::Hsl,
+  ];
+
+/// Describes the way around the color wheel taken when blending hues in the
+/// OkLCh, HSV and HSL color spaces.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HuePath { Shorter, Longer, Increasing, Decreasing }
+
+impl std::fmt::Display for HuePath {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     HuePath::Shorter => "Shorter hue", 
+     HuePath::Longer => "Longer hue", 
+     HuePath::Increasing => "Increasing hue", 
+     HuePath::Decreasing => "Decreasing hue", 
+    })
+  }
+}
+
+pub static ALL_HUE_PATHS : &[HuePath] = &[
+  HuePath::Shorter,
+  HuePath::Longer,
+  HuePath::Increasing,
+  HuePath::Decreasing,
+  ];
+
+/// Describes the curve along which each color channel moves between gradient
+/// peaks. `MonotoneCubic` never overshoots the peak colors, `CatmullRom` passes
+/// through the peaks with continuous slope and `BSpline` only approaches the
+/// peaks but is the smoothest. All curves pass through the start and end
+/// color.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradientCurve { Linear, MonotoneCubic, CatmullRom, BSpline }
+
+impl std::fmt::Display for GradientCurve {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     GradientCurve::Linear => "Linear curve", 
+     GradientCurve::MonotoneCubic => "Monotone cubic", 
+     GradientCurve::CatmullRom => "Catmull-Rom", 
+     GradientCurve::BSpline => "B-spline", 
+    })
+  }
+}
+
+pub static ALL_GRADIENT_CURVES : &[GradientCurve] = &[
+  GradientCurve::Linear,
+  GradientCurve::MonotoneCubic,
+  GradientCurve::CatmullRom,
+  GradientCurve::BSpline,
+  ];
+
+/// Describes how the colors of a gradient are blended between peaks.
+/// - `space` the color space in which colors are blended
+/// - `hue_path` the way hues are blended in hue based color spaces
+/// - `curve` the curve each channel of `space` follows between peaks
+#[derive (Clone, Copy, Debug)]
+pub struct GradientBlend {
+  pub space : ColorSpace,
+  pub hue_path : HuePath,
+  pub curve : GradientCurve,
+}
+
+impl Default for GradientBlend {
+  fn default() -> Self {
+    GradientBlend {
+      space : ColorSpace::Srgb,
+      hue_path : HuePath::Shorter,
+      curve : GradientCurve::Linear,
+    }
+  }
+}
+
 /// Describes the color gradient chosen for the picture. The gradient starts
-/// with `starting_color` and then linearly transitions between colors in
-/// `peaks` and ends with `end_color`. Features an option to smooth step
-/// transition (designed for Mandebrot).
+/// with `start_color`, passes the colors in `peaks` and ends with `end_color`.
+/// Between them the colors are blended as described by `blend`: in its color
+/// space and along its curve, which is a straight line only for the linear
+/// curve. With `smooth` the iteration count of escaped points is smoothed
+/// (designed for Mandelbrot). The `mapping` describes how indices are spread
+/// over the gradient.
 #[derive (Clone, Debug)]
 pub struct Gradient {
   pub start_color : (u8, u8, u8, u8),
   pub peaks : Vec<ColorPeak>,
   pub end_color : (u8, u8, u8, u8),
   pub smooth : bool,
+  pub mapping : GradientMapping,
+  pub blend : GradientBlend,
+}
+
+/// Describes how the colors of a layer are combined with the colors beneath
+/// it.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode { Normal, Multiply, Screen, Overlay, Add, Difference }
+
+impl std::fmt::Display for BlendMode {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     BlendMode::Normal => "Normal", 
+     BlendMode::Multiply => "Multiply", 
+     BlendMode::Screen => "Screen", 
+     BlendMode::Overlay => "Overlay", 
+     BlendMode::Add => "Add", 
+     BlendMode::Difference => "Difference", 
+    })
+  }
+}
+
+pub static ALL_BLEND_MODES : &[BlendMode] = &[
+  BlendMode::Normal,
+  BlendMode::Multiply,
+  BlendMode::Screen,
+  BlendMode::Overlay,
+  BlendMode::Add,
+  BlendMode::Difference,
+  ];
+
+/// Describes which points a layer colors. `Everywhere` does not mask the
+/// layer, the other masks restrict it to points that escaped or did not.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerMask { Everywhere, Exterior, Interior }
+
+impl std::fmt::Display for LayerMask {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     LayerMask::Everywhere => "No mask", 
+     LayerMask::Exterior => "Exterior only", 
+     LayerMask::Interior => "Interior only", 
+    })
+  }
+}
+
+pub static ALL_LAYER_MASKS : &[LayerMask] = &[
+  LayerMask::Everywhere,
+  LayerMask::Exterior,
+  LayerMask::Interior,
+  ];
+
+/// A coloring composited over the base coloring of the fractal.
+/// - `algorithm` the coloring algorithm of the layer
+/// - `gradient` the gradient the algorithm colors with
+/// - `opacity` how strongly the layer covers the colors beneath it, between 0
+///   and 1
+/// - `blend` the way the layer is combined with the colors beneath it
+/// - `mask` the points the layer colors
+#[derive (Clone, Debug)]
+pub struct Layer {
+  pub algorithm : Algorithm,
+  pub gradient : Gradient,
+  pub opacity : f64,
+  pub blend : BlendMode,
+  pub mask : LayerMask,
+}
+
+impl Default for Layer {
+  fn default() -> Self {
+    Layer {
+      algorithm : algorithms::DEFAULT_EXTERIOR,
+      gradient : Gradient::azul(),
+      opacity : 0.5,
+      blend : BlendMode::Normal,
+      mask : LayerMask::Exterior,
+    }
+  }
 }
 
-// Color Presets (These might be only temporarily here)
-#[derive (Clone, Copy)]
-pub enum GradientPreset { Azul, Svarog, Emperor, Gaia }
+/// Describes how the colored fractal is lit. `Off` leaves the colors as they
+/// are, `Lambert` only shades surfaces facing away from the light and
+/// `BlinnPhong` adds highlights.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightingModel { Off, Lambert, BlinnPhong }
 
+impl std::fmt::Display for LightingModel {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     LightingModel::Off => "No lighting", 
+     LightingModel::Lambert => "Lambert", 
+     LightingModel::BlinnPhong => "Blinn-Phong", 
+    })
+  }
+}
+
+pub static ALL_LIGHTING_MODELS : &[LightingModel] = &[
+  LightingModel::Off,
+  LightingModel::Lambert,
+  LightingModel::BlinnPhong,
+  ];
+
+/// Describes the fractal value used as the height of the lit surface, the
+/// logarithm of either the smoothed iteration count or the distance estimate.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightSource { SmoothIteration, DistanceEstimate }
+
+impl std::fmt::Display for HeightSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     HeightSource::SmoothIteration => "Height from iterations", 
+     HeightSource::DistanceEstimate => "Height from distance", 
+    })
+  }
+}
+
+pub static ALL_HEIGHT_SOURCES : &[HeightSource] = &[
+  HeightSource::SmoothIteration,
+  HeightSource::DistanceEstimate,
+  ];
+
+/// Describes the lighting of the colored fractal.
+/// - `model` the lighting model, or `Off`
+/// - `source` the value the height of the surface is taken from
+/// - `azimuth` direction of the light around the image in degrees
+/// - `elevation` angle of the light above the image in degrees
+/// - `height` scale of the surface height
+/// - `ambient` brightness of surfaces facing away from the light, between 0
+///   and 1
+/// - `specular` strength of Blinn-Phong highlights and `shininess` their
+///   sharpness
+#[derive (Clone, Copy, Debug)]
+pub struct Lighting {
+  pub model : LightingModel,
+  pub source : HeightSource,
+  pub azimuth : f64,
+  pub elevation : f64,
+  pub height : f64,
+  pub ambient : f64,
+  pub specular : f64,
+  pub shininess : f64,
+}
+
+impl Default for Lighting {
+  fn default() -> Self {
+    Lighting {
+      model : LightingModel::Off,
+      source : HeightSource::DistanceEstimate,
+      azimuth : 135.,
+      elevation : 45.,
+      height : 4.,
+      ambient : 0.3,
+      specular : 0.5,
+      shininess : 20.,
+    }
+  }
+}
+
+/// Describes which points of the image are transparent, for compositing
+/// renders over other artwork.
+/// - `interior` and `exterior` make all interior or all exterior points fully
+///   transparent
+/// - `fade` toggles fading, the value `fade_algorithm` computes for a point is
+///   its opacity (for example the smooth iteration count fades out far from
+///   the set)
+/// - `invert_fade` makes points with high values transparent instead
+/// - `fade_power` the exponent applied to the value, higher powers fade out
+///   faster
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct Transparency {
+  pub interior : bool,
+  pub exterior : bool,
+  pub fade : bool,
+  pub fade_algorithm : Algorithm,
+  pub invert_fade : bool,
+  pub fade_power : f64,
+}
+
+impl Default for Transparency {
+  fn default() -> Self {
+    Transparency {
+      interior : false,
+      exterior : false,
+      fade : false,
+      fade_algorithm : algorithms::DEFAULT_EXTERIOR,
+      invert_fade : false,
+      fade_power : 1.,
+    }
+  }
+}
+
+/// Describes how high precision colors are rounded to 8 bits. Dithering
+/// hides the banding of smooth gradients, `Ordered` with a Bayer pattern and
+/// `ErrorDiffusion` by passing rounding errors on to the neighbouring pixels
+/// (Floyd-Steinberg).
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dither { Off, Ordered, ErrorDiffusion }
+
+impl std::fmt::Display for Dither {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     Dither::Off => "No dithering", 
+     Dither::Ordered => "Ordered dithering", 
+     Dither::ErrorDiffusion => "Error diffusion", 
+    })
+  }
+}
+
+pub static ALL_DITHERS : &[Dither] = &[
+  Dither::Off,
+  Dither::Ordered,
+  Dither::ErrorDiffusion,
+  ];
+
+/// Describes the adjustments applied to the finished image, in this order.
+/// - `black_level` and `white_level` the input levels mapped to black and
+///   white, between 0 and 1
+/// - `brightness` added to every channel, between -1 and 1, and `contrast`
+///   the factor by which channels move away from the middle gray
+/// - `gamma` the exponent `1 / gamma` is applied to every channel
+/// - `hue_shift` rotation of all hues in degrees and `saturation` the factor
+///   by which colors move away from gray
+/// - `sharpen` strength of the unsharp mask
+/// - `vignette` how much the corners are darkened, between 0 and 1
+/// - `dither` how the result is rounded to 8 bits
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct PostProcess {
+  pub black_level : f64,
+  pub white_level : f64,
+  pub brightness : f64,
+  pub contrast : f64,
+  pub gamma : f64,
+  pub hue_shift : f64,
+  pub saturation : f64,
+  pub sharpen : f64,
+  pub vignette : f64,
+  pub dither : Dither,
+}
+
+impl Default for PostProcess {
+  fn default() -> Self {
+    PostProcess {
+      black_level : 0.,
+      white_level : 1.,
+      brightness : 0.,
+      contrast : 1.,
+      gamma : 1.,
+      hue_shift : 0.,
+      saturation : 1.,
+      sharpen : 0.,
+      vignette : 0.,
+      dither : Dither::Off,
+    }
+  }
+}
+
+/// Describes how colors extracted from a photo are ordered along the
+/// gradient, from dark to light or around the color wheel.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteOrder { Luminance, Hue }
+
+impl std::fmt::Display for PaletteOrder {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     PaletteOrder::Luminance => "By luminance", 
+     PaletteOrder::Hue => "By hue", 
+    })
+  }
+}
+
+pub static ALL_PALETTE_ORDERS : &[PaletteOrder] = &[
+  PaletteOrder::Luminance,
+  PaletteOrder::Hue,
+  ];
+
+/// Describes which hues a randomly generated gradient uses: hues close to each
+/// other, two opposite hues, three evenly spread hues or a single hue.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorScheme { Analogous, Complementary, Triadic, Monochrome }
+
+impl std::fmt::Display for ColorScheme {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     ColorScheme::Analogous => "Analogous", 
+     ColorScheme::Complementary => "Complementary", 
+     ColorScheme::Triadic => "Triadic", 
+     ColorScheme::Monochrome => "Monochrome", 
+    })
+  }
+}
+
+pub static ALL_COLOR_SCHEMES : &[ColorScheme] = &[
+  ColorScheme::Analogous,
+  ColorScheme::Complementary,
+  ColorScheme::Triadic,
+  ColorScheme::Monochrome,
+  ];
+
+/// Describes a randomly generated gradient. The same `scheme` and `seed`
+/// always generate the same gradient.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GradientSeed {
+  pub scheme : ColorScheme,
+  pub seed : u32,
+}
+
+// Built-in presets, the preset library lives in `fractal::library`
 impl Gradient {
 
   pub fn azul() -> Gradient {
@@ -121,6 +726,8 @@ impl Gradient {
         ),
       end_color : (0, 0, 0, 255),
       smooth : true,
+      mapping : GradientMapping::default(),
+      blend : GradientBlend::default(),
     }}
 
   pub fn svarog() -> Gradient {
@@ -134,6 +741,8 @@ impl Gradient {
         ),
       end_color : (255, 255, 255, 255),
       smooth : true,
+      mapping : GradientMapping::default(),
+      blend : GradientBlend::default(),
     }}
 
   pub fn emperor() -> Gradient {
@@ -147,6 +756,8 @@ impl Gradient {
         ),
       end_color : (255, 255, 255, 255),
       smooth : true,
+      mapping : GradientMapping::default(),
+      blend : GradientBlend::default(),
     }}
 
   pub fn gaia() -> Gradient {
@@ -160,14 +771,14 @@ impl Gradient {
         ),
       end_color : (255, 255, 255, 255),
       smooth : true,
+      mapping : GradientMapping::default(),
+      blend : GradientBlend::default(),
     }}
 }
 
 
-pub static DEFAULT_GRADIENT_PRESET : GradientPreset = GradientPreset::Gaia;
-
 impl Default for Gradient {
-    fn default() -> Self { Gradient::gaia_gradient() }
+    fn default() -> Self { Gradient::gaia() }
   }
 
 //==============================================================================
@@ -182,7 +793,11 @@ impl Default for Gradient {
 ///   during iteration, we assume divergence
 /// - `iteration_style` specifies Julia or Mandelbrot iteraton
 /// - `iterator_kind` specifies the function used in iteration
-#[derive(Copy, Clone)]
+/// - `trap` the orbit trap to which the distance of the orbit is tracked
+/// - `stripe_density` the frequency `k` of `sin(k arg z)` in stripe averages
+/// - `find_cycles` toggles the search for attracting cycles of points that
+///   never escaped, which only some coloring algorithms need
+#[derive(Copy, Clone, PartialEq)]
 pub struct FracArgs {
   pub field : Field,
   pub c_re  : f64, 
@@ -191,6 +806,9 @@ pub struct FracArgs {
   pub iter_bound      : f64, 
   pub iteration_style : IterationStyle,
   pub iterator_kind   : IteratorKind,
+  pub trap            : OrbitTrap,
+  pub stripe_density  : f64,
+  pub find_cycles     : bool,
 }
 
 impl Default for FracArgs {
@@ -203,23 +821,229 @@ impl Default for FracArgs {
       iter_bound      : 10., 
       iteration_style : IterationStyle::Julia,
       iterator_kind   : IteratorKind::Square,
+      trap            : OrbitTrap::default(),
+      stripe_density  : 5.,
+      find_cycles     : false,
     }
   }
 }
 
 /// Arguments required for coloring a fractal.
 /// - `gradient` specifies the gradient which is used
-/// - `better_resize` toggles the use of a resizer before the image is passed
-///   to the UI for display
+/// - `exterior` the coloring algorithm for points that escaped
+/// - `trap_width` the orbit trap distance mapped to the end of the gradient
+/// - `texture` optional image texture used as an orbit trap on top of the
+///   gradient coloring
+/// - `texture_hit` specifies how multiple texture hits are combined
+/// - `decomposition` number of angle sectors in n-ary decomposition and number
+///   of field lines
+/// - `angle_strength` how strongly angle based colorings affect the gradient
+///   coloring, between 0 and 1
+/// - `interior` the coloring algorithm for points that never escaped
+/// - `interior_gradient` the gradient used for coloring the interior
+/// - `equalize` toggles histogram equalization of the exterior coloring
+/// - `equalize_clip` fraction of the lowest and of the highest values ignored
+///   by histogram equalization
+/// - `seed` the seed `gradient` was randomly generated from, if any
+/// - `lighting` the lighting applied after coloring
+/// - `layers` colorings composited over the base coloring, from the bottom up
+/// - `post` adjustments applied to the finished image
+/// - `transparency` the points of the finished image that are transparent
 #[derive(Clone)]
 pub struct ImageArgs {
   pub gradient : Gradient,
+  pub exterior : Algorithm,
+  pub trap_width : f64,
+  pub texture : Option<TextureTrap>,
+  pub texture_hit : TextureHit,
+  pub decomposition : usize,
+  pub angle_strength : f64,
+  pub interior : Algorithm,
+  pub interior_gradient : Gradient,
+  pub equalize : bool,
+  pub equalize_clip : f64,
+  pub seed : Option<GradientSeed>,
+  pub lighting : Lighting,
+  pub layers : Vec<Layer>,
+  pub post : PostProcess,
+  pub transparency : Transparency,
 }
 
 impl Default for ImageArgs {
   fn default() -> Self {
     ImageArgs {
       gradient : Gradient::default(),
+      exterior : algorithms::DEFAULT_EXTERIOR,
+      trap_width : 1.,
+      texture : None,
+      texture_hit : TextureHit::First,
+      decomposition : 4,
+      angle_strength : 0.5,
+      interior : algorithms::DEFAULT_INTERIOR,
+      interior_gradient : Gradient::azul(),
+      equalize : false,
+      equalize_clip : 0.01,
+      seed : None,
+      lighting : Lighting::default(),
+      layers : Vec::new(),
+      post : PostProcess::default(),
+      transparency : Transparency::default(),
+    }
+  }
+}
+
+impl ImageArgs {
+  /// Returns whether any coloring of the image reads the attracting cycles of
+  /// points, so that the fractal has to be computed with `find_cycles`.
+  pub fn needs_cycles(&self) -> bool {
+    self.interior.0.uses_cycles()
+      || self.layers.iter().any(|layer| layer.algorithm.0.uses_cycles())
+      || (self.transparency.fade && self.transparency.fade_algorithm.0.uses_cycles())
+  }
+}
+
+//==============================================================================
+// Export arguments
+//==============================================================================
+
+/// File formats images can be exported in. `Pfm` (portable float map) holds
+/// linear light `f32` channels without alpha.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat { Png, Jpeg, Tiff, Bmp, Pfm }
+
+impl ExportFormat {
+  /// The file extension of the format.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ExportFormat::Png => "png",
+      ExportFormat::Jpeg => "jpg",
+      ExportFormat::Tiff => "tiff",
+      ExportFormat::Bmp => "bmp",
+      ExportFormat::Pfm => "pfm",
+    }
+  }
+}
+
+impl std::fmt::Display for ExportFormat {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     ExportFormat::Png => "PNG", 
+     ExportFormat::Jpeg => "JPEG", 
+     ExportFormat::Tiff => "TIFF", 
+     ExportFormat::Bmp => "BMP", 
+     ExportFormat::Pfm => "PFM (float)", 
+    })
+  }
+}
+
+pub static ALL_EXPORT_FORMATS : &[ExportFormat] = &[
+  ExportFormat::Png,
+  ExportFormat::Jpeg,
+  ExportFormat::Tiff,
+  ExportFormat::Bmp,
+  ExportFormat::Pfm,
+  ];
+
+/// Describes how exported files are named so that earlier exports are never
+/// overwritten. `Numbered` appends the first free number to the name,
+/// `Timestamped` the time of the export.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileNaming { Numbered, Timestamped }
+
+impl std::fmt::Display for FileNaming {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     FileNaming::Numbered => "Numbered", 
+     FileNaming::Timestamped => "Timestamped", 
+    })
+  }
+}
+
+pub static ALL_FILE_NAMINGS : &[FileNaming] = &[
+  FileNaming::Numbered,
+  FileNaming::Timestamped,
+  ];
+
+/// File formats raw fractal data can be exported in. `Binary` files can be
+/// loaded back for recoloring, see `fractal::raw` for their layout.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataFormat { Binary, Npy, Csv }
+
+impl DataFormat {
+  /// The file extension of the format.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      DataFormat::Binary => "frac",
+      DataFormat::Npy => "npy",
+      DataFormat::Csv => "csv",
+    }
+  }
+}
+
+impl std::fmt::Display for DataFormat {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+     DataFormat::Binary => "Raw data", 
+     DataFormat::Npy => "NumPy", 
+     DataFormat::Csv => "CSV", 
+    })
+  }
+}
+
+pub static ALL_DATA_FORMATS : &[DataFormat] = &[
+  DataFormat::Binary,
+  DataFormat::Npy,
+  DataFormat::Csv,
+  ];
+
+/// Arguments required for exporting an image.
+/// - `directory` the directory the image is written into
+/// - `name` the file name, before the number or timestamp and the extension
+/// - `naming` how the number or timestamp is chosen
+/// - `format` the file format and `jpeg_quality` the quality of JPEG files,
+///   between 1 and 100
+/// - `sixteen_bit` toggles 16 bits per channel for PNG and TIFF files
+/// - `pixel_size` the side of the exported image in pixels, the fractal is
+///   computed anew at this resolution
+/// - `at_preview` exports the shown fractal at the resolution of the preview
+///   instead of `pixel_size`
+/// - `data_format` the file format of exported raw fractal data
+#[derive(Clone, Debug)]
+pub struct ExportArgs {
+  pub directory : String,
+  pub name : String,
+  pub naming : FileNaming,
+  pub format : ExportFormat,
+  pub jpeg_quality : u8,
+  pub sixteen_bit : bool,
+  pub pixel_size : usize,
+  pub at_preview : bool,
+  pub data_format : DataFormat,
+}
+
+impl ExportArgs {
+  /// Returns whether the image is saved with more than 8 bits per channel.
+  pub fn high_precision(&self) -> bool {
+    match self.format {
+      ExportFormat::Pfm => true,
+      ExportFormat::Png | ExportFormat::Tiff => self.sixteen_bit,
+      ExportFormat::Jpeg | ExportFormat::Bmp => false,
+    }
+  }
+}
+
+impl Default for ExportArgs {
+  fn default() -> Self {
+    ExportArgs {
+      directory : ".".to_string(),
+      name : "fractal_image".to_string(),
+      naming : FileNaming::Numbered,
+      format : ExportFormat::Png,
+      jpeg_quality : 90,
+      sixteen_bit : false,
+      pixel_size : 2000,
+      at_preview : false,
+      data_format : DataFormat::Binary,
     }
   }
 }
@@ -3,7 +3,84 @@
 //==============================================================================
 use rayon::prelude::*;
 
+use error::{Error, Result};
 use fractal::definitions::*;
+use fractal::post;
+
+/// Longest attracting cycle searched for in non-escaping points.
+const MAX_PERIOD : usize = 64;
+
+/// Distance at which an orbit point `z` is considered to have closed the
+/// cycle, relative to `1 + |z|`.
+const PERIOD_TOLERANCE : f64 = 1e-4;
+
+/// Extra iterations of a point that never escaped before its cycle is searched
+/// for, which bring slowly attracted orbits closer to the cycle.
+const SETTLE_STEPS : usize = 256;
+
+/// Most memory a single render may take, in bytes.
+pub const MEMORY_LIMIT : u64 = 4 << 30;
+
+/// 8 bit BGRA images kept alongside the raw fractal while coloring: the
+/// colored image, its stored copy and the frames of the palette cycle.
+const IMAGE_COPIES : u64 = 4;
+
+//==============================================================================
+// Memory
+//==============================================================================
+
+/// Returns the bytes per point that coloring with `img_args` takes besides the
+/// raw fractal: the high precision image, the 8 bit images, the buffers of
+/// every stage `img_args` enables and what the palette cycle keeps of them.
+fn coloring_bytes(img_args : &ImageArgs) -> u64 {
+  // The palette cycle keeps the gradient index of every point
+  let mut bytes = 16 + 4 * IMAGE_COPIES + 16;
+  if img_args.equalize {
+    bytes += 8;
+  }
+  if !img_args.layers.is_empty() {
+    // Layers are colored one at a time, the palette cycle keeps all of them
+    bytes += 16 * (img_args.layers.len() as u64 + 1);
+  }
+  if img_args.texture.is_some() {
+    // Hits kept with the fractal and in the palette cycle
+    bytes += 2 * 32;
+  }
+  if img_args.lighting.model != LightingModel::Off {
+    // Heights and shading, the palette cycle keeps the shading
+    bytes += 40 + 24;
+  }
+  if !post::is_identity(&img_args.post) {
+    bytes += if img_args.post.sharpen > 0. { 48 } else { 24 };
+  }
+  if img_args.post.dither == Dither::ErrorDiffusion {
+    bytes += 32;
+  }
+  let transparency = img_args.transparency;
+  if transparency.interior || transparency.exterior || transparency.fade {
+    // Opacity, kept by the palette cycle as well
+    bytes += 2 * 8;
+  }
+  bytes
+}
+
+/// Estimates the memory in bytes needed for computing a fractal with
+/// `pixel_size` pixels per side and coloring it with `img_args`.
+pub fn memory_estimate(pixel_size : usize, img_args : &ImageArgs) -> u64 {
+  let points = pixel_size as u64 * pixel_size as u64;
+  let per_point = std::mem::size_of::<FracPoint>() as u64 + coloring_bytes(img_args);
+  points.saturating_mul(per_point)
+}
+
+/// Checks that a fractal with `pixel_size` pixels per side colored with
+/// `img_args` fits into `MEMORY_LIMIT`.
+pub fn check_memory(pixel_size : usize, img_args : &ImageArgs) -> Result<()> {
+  let bytes = memory_estimate(pixel_size, img_args);
+  if bytes > MEMORY_LIMIT {
+    return Err(Error::TooLarge{pixel_size : pixel_size, bytes : bytes, limit : MEMORY_LIMIT})
+  }
+  Ok(())
+}
 
 //==============================================================================
 // Pixels to complex
@@ -30,59 +107,227 @@ pub fn point_to_complex (field : &Field, i : usize, j : usize) -> (f64, f64) {
 
 /// Accepts a starting point of the iteration `z = (z_re, z_im)` and the
 /// iteration constant `c = (c_re, c_im)`. Using the function `f` it iterates
-/// `z = f(z, c)` until a termination condition is met (`max_steps` or the
-/// absolute value of `z` goes beyond `iter_bound`).
+/// `z = f(z, c)` until a termination condition is met (`args.steps` or the
+/// absolute value of `z` goes beyond `args.iter_bound`).
+///
+/// Alongside `z` the derivative `dz` is tracked with `df`, which returns
+/// `f'(z) * dz`. The derivative starts at `d_z` and `d_c` is added on every
+/// step (`1.` and `0.` for derivation by `z` in Julia style, `0.` and `1.` for
+/// derivation by `c` in Mandelbrot style). It is used for distance estimation.
+///
+/// The minimal distance of the orbit to the orbit trap `args.trap` is tracked
+/// as well, together with the stripe and triangle inequality averages and the
+/// minimal absolute value of the orbit. For non-escaping points the attracting
+/// cycle is searched for as well if `args.find_cycles` is set.
 fn iterate_point (
   z_re : f64, z_im : f64,
   c_re : f64, c_im : f64,
-  max_steps : usize, iter_bound : f64,
-  f : impl Fn(f64, f64, f64, f64) -> (f64, f64) )
+  d_z : f64, d_c : f64,
+  args : &FracArgs,
+  f : impl Fn(f64, f64, f64, f64) -> (f64, f64),
+  df : impl Fn(f64, f64, f64, f64) -> (f64, f64) )
 -> FracPoint {
+  let max_steps = args.steps;
+  let iter_bound = args.iter_bound;
 
   // Loop mutables
   let mut step = 0; 
   let mut re = z_re;
   let mut im = z_im;
+  let mut d_re = d_z;
+  let mut d_im = 0.0;
+  let mut trap_dist = std::f64::INFINITY;
+  let mut trap_step = 0;
+  // Sums of averaged terms and their last terms
+  let c_abs = (c_re * c_re + c_im * c_im).sqrt();
+  let mut stripe_sum = 0.;
+  let mut stripe_last = 0.;
+  let mut triangle_sum = 0.;
+  let mut triangle_last = 0.;
+  let mut triangle_count = 0;
+  let mut min_abs = std::f64::INFINITY;
+  let mut min_step = 0;
 
-  // Iterate `z = f(z, c)`
+  // Iterate `z = f(z, c)` and `dz = f'(z) * dz + d_c`
   while step < max_steps && (re * re + im * im) < iter_bound {
+    let (new_d_re, new_d_im) = df(re, im, d_re, d_im);
+    d_re = new_d_re + d_c;
+    d_im = new_d_im;
+    let (new_re, new_im) = f(re, im, c_re, c_im);
+    re = new_re;
+    im = new_im;
+    step += 1;
+
+    let dist = trap_distance(&args.trap, re, im);
+    if dist < trap_dist {
+      trap_dist = dist;
+      trap_step = step;
+    }
+
+    stripe_last = 0.5 * (args.stripe_density * im.atan2(re)).sin() + 0.5;
+    stripe_sum += stripe_last;
+
+    // Triangle inequality bounds of `|z|` given `|z - c|` and `|c|`
+    let diff = ((re - c_re) * (re - c_re) + (im - c_im) * (im - c_im)).sqrt();
+    let lower = (diff - c_abs).abs();
+    let upper = diff + c_abs;
+    // Steps without a range are left out, so the last term is the last
+    // counted one
+    if upper > lower {
+      triangle_last = ((re * re + im * im).sqrt() - lower) / (upper - lower);
+      triangle_sum += triangle_last;
+      triangle_count += 1;
+    }
+
+    let abs_val = (re * re + im * im).sqrt();
+    if abs_val < min_abs {
+      min_abs = abs_val;
+      min_step = step;
+    }
+  }
+
+  let (stripe, stripe_prev) = averages(stripe_sum, stripe_last, step);
+  let (triangle, triangle_prev) = 
+    averages(triangle_sum, triangle_last, triangle_count);
+
+  let dist = 
+    if step < max_steps {
+      distance_estimate(re, im, d_re, d_im)
+    } else {
+      0.
+    };
+
+  let (period, multiplier) = 
+    if step < max_steps {
+      (0, 0.)
+    } else if !args.find_cycles {
+      // Same as when no cycle is found
+      (0, 1.)
+    } else {
+      attracting_cycle(re, im, c_re, c_im, &f, &df)
+    };
+
+  FracPoint{
+    n: step, re, im, dist, trap_dist, trap_step,
+    stripe, stripe_prev, triangle, triangle_prev,
+    min_abs, min_step, period, multiplier,
+  }
+}
+
+/// Continues the iteration of the point `z = (re, im)` for `SETTLE_STEPS`
+/// and then until it returns close to where it was. Returns the period of the
+/// cycle together with the absolute value of its multiplier (the derivative of
+/// the iteration along the whole cycle). If no cycle up to `MAX_PERIOD` is
+/// found, the period is `0` and the multiplier `1.`.
+fn attracting_cycle(
+  re : f64, im : f64,
+  c_re : f64, c_im : f64,
+  f : impl Fn(f64, f64, f64, f64) -> (f64, f64),
+  df : impl Fn(f64, f64, f64, f64) -> (f64, f64) )
+-> (usize, f64) {
+  let (mut re, mut im) = (re, im);
+  for _ in 0..SETTLE_STEPS {
     let (new_re, new_im) = f(re, im, c_re, c_im);
     re = new_re;
     im = new_im;
-    step += 1 
+  }
+  let tolerance = PERIOD_TOLERANCE * (1. + (re * re + im * im).sqrt());
+  let (mut z_re, mut z_im) = (re, im);
+  let (mut d_re, mut d_im) = (1., 0.);
+
+  for period in 1..(MAX_PERIOD + 1) {
+    let (new_d_re, new_d_im) = df(z_re, z_im, d_re, d_im);
+    d_re = new_d_re;
+    d_im = new_d_im;
+    let (new_re, new_im) = f(z_re, z_im, c_re, c_im);
+    z_re = new_re;
+    z_im = new_im;
+
+    let gap = (z_re - re) * (z_re - re) + (z_im - im) * (z_im - im);
+    if gap < tolerance * tolerance {
+      return (period, (d_re * d_re + d_im * d_im).sqrt())
+    }
   }
 
-  (step, re, im)
+  (0, 1.)
+}
+
+/// Returns the average of `count` terms with the sum `sum` and the average of
+/// the same terms without the `last` one.
+fn averages(sum : f64, last : f64, count : usize) -> (f64, f64) {
+  let avg = if count > 0 { sum / count as f64 } else { 0. };
+  let avg_prev = if count > 1 { (sum - last) / (count - 1) as f64 } else { avg };
+  (avg, avg_prev)
+}
+
+/// Estimates the distance of an escaped point to the fractal from the final
+/// value of the iteration `z` and its derivative `dz` as `|z| ln|z| / |dz|`.
+/// The estimate improves with larger `iter_bound`.
+fn distance_estimate(re : f64, im : f64, d_re : f64, d_im : f64) -> f64 {
+  let z_abs = (re * re + im * im).sqrt();
+  let dz_abs = (d_re * d_re + d_im * d_im).sqrt();
+  if dz_abs > 0. {
+    (z_abs * z_abs.ln() / dz_abs).max(0.)
+  } else {
+    std::f64::INFINITY
+  }
 }
 
 /// Calculates a Julia style iteration for the function `f` using fractal
 /// arguments `args` (which include the constant `c`). The starting point of
-/// iteration is `z`.
+/// iteration is `z`. The derivative `df` is taken with respect to `z`.
 fn julia_iterate (
   z : (f64, f64), 
   f : impl Fn(f64, f64, f64, f64) -> (f64, f64), 
+  df : impl Fn(f64, f64, f64, f64) -> (f64, f64), 
   args : &FracArgs) 
 -> FracPoint {
 
   let (z_re, z_im) = z;
   let (c_re, c_im) = (args.c_re, args.c_im);
 
-  iterate_point(z_re, z_im, c_re, c_im, args.steps, args.iter_bound, f)
+  iterate_point(z_re, z_im, c_re, c_im, 1.0, 0.0, args, f, df)
 }
 
 /// Calculates a Mandelbrot style iteration for the function `f` using fractal
 /// arguments `args`. The starting point of iteration is `(0., 0.)` and the
-/// constant `c` is set to `z`.
+/// constant `c` is set to `z`. The derivative `df` is taken with respect to
+/// `c`.
 fn mandelbrot_iterate (
   z : (f64, f64), 
   f : impl Fn(f64, f64, f64, f64) -> (f64, f64), 
+  df : impl Fn(f64, f64, f64, f64) -> (f64, f64), 
   args : &FracArgs) 
 -> FracPoint {
 
   let (z_re, z_im) = (0.0, 0.0);
   let (c_re, c_im) = z;
   
-  iterate_point(z_re, z_im, c_re, c_im, args.steps, args.iter_bound, f)
+  iterate_point(z_re, z_im, c_re, c_im, 0.0, 1.0, args, f, df)
+}
+
+//==============================================================================
+// Orbit traps
+//==============================================================================
+
+/// Returns the distance of the point `(re, im)` to the orbit trap `trap`.
+fn trap_distance(trap : &OrbitTrap, re : f64, im : f64) -> f64 {
+  let OrbitTrap {shape, radius, angle, ..} = *trap;
+  let x = re - trap.re;
+  let y = im - trap.im;
+
+  // Distances to the line through the center in the direction of `angle` and
+  // to the line perpendicular to it
+  let along = (x * angle.sin() - y * angle.cos()).abs();
+  let across = (x * angle.cos() + y * angle.sin()).abs();
+
+  match shape {
+    TrapShape::Point => (x * x + y * y).sqrt(),
+    TrapShape::Line => along,
+    TrapShape::Cross => along.min(across),
+    TrapShape::Circle => ((x * x + y * y).sqrt() - radius).abs(),
+    TrapShape::Stalks => x.abs().min(y.abs()),
+  }
 }
 
 //==============================================================================
@@ -119,6 +364,51 @@ fn ship_iterator(re : f64, im : f64, c_re : f64, c_im : f64) -> (f64, f64) {
     (new_re, new_im)
   }
 
+//==============================================================================
+// Iterator derivatives
+//==============================================================================
+
+/// Derivative of `z ^ 2 + c` applied to `dz`, i.e. `2 z dz`.
+fn square_derivative(re : f64, im : f64, d_re : f64, d_im : f64) -> (f64, f64) {
+  let new_d_re = 2.0 * (re * d_re - im * d_im);
+  let new_d_im = 2.0 * (re * d_im + im * d_re);
+  (new_d_re, new_d_im)
+}
+
+/// Derivative of `z ^ 3 + c` applied to `dz`, i.e. `3 z^2 dz`.
+fn cube_derivative(re : f64, im : f64, d_re : f64, d_im : f64) -> (f64, f64) {
+  let sq_re = 3.0 * (re * re - im * im);
+  let sq_im = 3.0 * (2.0 * re * im);
+  let new_d_re = sq_re * d_re - sq_im * d_im;
+  let new_d_im = sq_re * d_im + sq_im * d_re;
+  (new_d_re, new_d_im)
+}
+
+/// Derivative of the inverse iteration applied to `dz`. The iteration squares
+/// `z / |z|^2`, which is `conj(z) ^ -1`, so it computes `conj(z) ^ -2 + c`.
+/// That is not holomorphic, but it is a holomorphic function of `conj(z)`, so
+/// the derivative is `-2 conj(z)^-3 conj(dz)`.
+fn inverse_derivative(re : f64, im : f64, d_re : f64, d_im : f64) -> (f64, f64) {
+  let size = (re * re + im * im).max(f64::MIN_POSITIVE);
+  // `w = conj(z) ^ -1`
+  let (w_re, w_im) = (re / size, im / size);
+  let cb_re = -2.0 * (w_re * w_re * w_re - 3.0 * w_re * w_im * w_im);
+  let cb_im = -2.0 * (3.0 * w_re * w_re * w_im - w_im * w_im * w_im);
+  // Multiply by `conj(dz)`
+  let new_d_re = cb_re * d_re + cb_im * d_im;
+  let new_d_im = -cb_re * d_im + cb_im * d_re;
+  (new_d_re, new_d_im)
+}
+
+/// Derivative of the Burning Ship iteration applied to `dz`. The absolute
+/// values only flip the signs of the components of `dz`, after which the
+/// square rule `2 z dz` is used on `(|re|, |im|)`.
+fn ship_derivative(re : f64, im : f64, d_re : f64, d_im : f64) -> (f64, f64) {
+  let d_re = d_re * re.signum();
+  let d_im = d_im * im.signum();
+  square_derivative(re.abs(), im.abs(), d_re, d_im)
+}
+
 /// Function that sets the values of a mutable matrix row `row` to fractal
 /// points obtained from `iter_fun` using fractal arguments `args`. The row
 /// number is required to calculate complex points that the row represents.
@@ -128,20 +418,20 @@ fn compute_row(
   row : &mut [FracPoint],
   row_num : usize,
   args : FracArgs,
-  iter_fun : impl Fn(f64, f64) -> (usize, f64, f64),
+  iter_fun : impl Fn(f64, f64) -> FracPoint,
 ){
   for col_num in 0..args.field.pixel_size{
     let (re, im) = point_to_complex(&args.field, col_num, row_num);
-    let (n, re, im) = iter_fun(re, im);
-    row[col_num] = (n, re, im);
+    row[col_num] = iter_fun(re, im);
   }
 }
 
 /// Renders raw fractal data as specified by fractal arguments `args`. The rows
-/// are calculated in parallel.
+/// are calculated in parallel. Callers check with `check_memory` beforehand
+/// that the fractal fits into memory together with its coloring.
 pub fn compute_fractal(args : FracArgs) -> RawFrac {
   let px_size = args.field.pixel_size;
-  let mut matrix = vec![(0, 0., 0.); px_size * px_size];
+  let mut matrix = vec![FracPoint::default(); px_size * px_size];
 
   let iteration_fn = match args.iteration_style {
     IterationStyle::Julia => {julia_iterate}
@@ -153,15 +443,161 @@ pub fn compute_fractal(args : FracArgs) -> RawFrac {
     IteratorKind::Inverse => {inverse_iterator}
     IteratorKind::Ship => {ship_iterator}
   };
+  let derivative_fn = match args.iterator_kind {
+    IteratorKind::Square => {square_derivative}
+    IteratorKind::Cube => {cube_derivative}
+    IteratorKind::Inverse => {inverse_derivative}
+    IteratorKind::Ship => {ship_derivative}
+  };
   
   {// Mutable borrow scope
-    let rows : Vec<(usize, &mut [(usize, f64, f64)])> = 
+    let rows : Vec<(usize, &mut [FracPoint])> = 
     matrix.chunks_mut(px_size).enumerate().collect();
 
-    let iter_fun = |re, im|{iteration_fn((re, im), iterator_fn, &args)};
+    let iter_fun = |re, im|{
+      iteration_fn((re, im), iterator_fn, derivative_fn, &args)};
     rows.into_par_iter()
     .for_each(|(row_num, row)|{compute_row(row, row_num, args, iter_fun)});
   }
 
   matrix
 }
+
+/// Follows the orbit of every point as specified by fractal arguments `args`
+/// and folds the orbit points into a single value with `visit`, starting with
+/// `init`. Used for colorings that depend on the whole orbit instead of a
+/// single `FracPoint`. The rows are calculated in parallel.
+pub fn fold_orbits<T>(
+  args : FracArgs,
+  init : T,
+  visit : impl Fn(T, f64, f64) -> T + Sync)
+-> Vec<T> 
+where T : Copy + Send + Sync {
+  let px_size = args.field.pixel_size;
+  let mut matrix = vec![init; px_size * px_size];
+
+  let iterator_fn = match args.iterator_kind {
+    IteratorKind::Square => {square_iterator}
+    IteratorKind::Cube => {cube_iterator}
+    IteratorKind::Inverse => {inverse_iterator}
+    IteratorKind::Ship => {ship_iterator}
+  };
+
+  // Follows the orbit starting in `z` with the constant `c`
+  let fold_orbit = |z : (f64, f64), c : (f64, f64)| {
+    let (mut re, mut im) = z;
+    let (c_re, c_im) = c;
+    let mut acc = init;
+    let mut step = 0;
+    while step < args.steps && (re * re + im * im) < args.iter_bound {
+      let (new_re, new_im) = iterator_fn(re, im, c_re, c_im);
+      re = new_re;
+      im = new_im;
+      acc = visit(acc, re, im);
+      step += 1
+    }
+    acc
+  };
+
+  matrix.par_chunks_mut(px_size).enumerate().for_each(|(row_num, row)|{
+    for col_num in 0..px_size {
+      let point = point_to_complex(&args.field, col_num, row_num);
+      row[col_num] = match args.iteration_style {
+        IterationStyle::Julia => fold_orbit(point, (args.c_re, args.c_im)),
+        IterationStyle::Mandelbrot => fold_orbit((0., 0.), point),
+      };
+    }
+  });
+
+  matrix
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Points off the axes, where the ship iteration is differentiable.
+  static POINTS : &[(f64, f64)] = &[(0.7, 0.4), (-0.6, 0.9), (-0.5, -0.8), (1.1, -0.3)];
+
+  /// Checks `df` against central differences of `f` along several directions.
+  fn check_derivative(
+    f : impl Fn(f64, f64, f64, f64) -> (f64, f64),
+    df : impl Fn(f64, f64, f64, f64) -> (f64, f64)) {
+    let h = 1e-6;
+    for &(re, im) in POINTS {
+      for &(d_re, d_im) in &[(1., 0.), (0., 1.), (0.6, -0.8)] {
+        let (plus_re, plus_im) = f(re + h * d_re, im + h * d_im, 0.3, -0.2);
+        let (minus_re, minus_im) = f(re - h * d_re, im - h * d_im, 0.3, -0.2);
+        let expected = ((plus_re - minus_re) / (2. * h), (plus_im - minus_im) / (2. * h));
+        let found = df(re, im, d_re, d_im);
+        assert!((found.0 - expected.0).abs() < 1e-6 * (1. + expected.0.abs()),
+          "{:?} at {:?} along {:?}, expected {:?}", found, (re, im), (d_re, d_im), expected);
+        assert!((found.1 - expected.1).abs() < 1e-6 * (1. + expected.1.abs()),
+          "{:?} at {:?} along {:?}, expected {:?}", found, (re, im), (d_re, d_im), expected);
+      }
+    }
+  }
+
+  /// Iterates the Julia set point `z` with the constant `c` and searches for
+  /// its attracting cycle.
+  fn cycle_of(z : (f64, f64), c : (f64, f64)) -> FracPoint {
+    let mut args = FracArgs::default();
+    args.find_cycles = true;
+    iterate_point(z.0, z.1, c.0, c.1, 1., 0., &args, square_iterator, square_derivative)
+  }
+
+  #[test]
+  fn basilica_has_period_two() {
+    for &z in &[(0., 0.), (0.1, 0.05), (-0.2, 0.1)] {
+      let point = cycle_of(z, (-1., 0.));
+      assert_eq!(point.period, 2, "from {:?}", z);
+      // The cycle passes through the critical point
+      assert!(point.multiplier < 1e-6);
+    }
+  }
+
+  #[test]
+  fn slowly_attracted_points_find_their_cycle() {
+    // The fixed point attracts with the multiplier -0.98, after the usual
+    // steps the orbit is still about 0.01 away from it
+    let point = cycle_of((0., 0.), (-0.7301, 0.));
+    assert_eq!(point.period, 1);
+    assert!((point.multiplier - 0.98).abs() < 1e-3);
+  }
+
+  #[test]
+  fn every_layer_counts_towards_memory() {
+    let mut img_args = ImageArgs::default();
+    let plain = memory_estimate(1000, &img_args);
+    img_args.layers.push(Layer::default());
+    let one = memory_estimate(1000, &img_args);
+    img_args.layers.push(Layer::default());
+    let two = memory_estimate(1000, &img_args);
+    assert!(plain < one && one < two);
+    assert_eq!(two - one, 16 * 1000 * 1000);
+  }
+
+  #[test]
+  fn square_derivative_matches_differences() {
+    check_derivative(square_iterator, square_derivative);
+  }
+
+  #[test]
+  fn cube_derivative_matches_differences() {
+    check_derivative(cube_iterator, cube_derivative);
+  }
+
+  #[test]
+  fn inverse_derivative_matches_differences() {
+    check_derivative(inverse_iterator, inverse_derivative);
+  }
+
+  #[test]
+  fn ship_derivative_matches_differences() {
+    check_derivative(ship_iterator, ship_derivative);
+  }
+}
@@ -0,0 +1,264 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::{ImageBuffer, ImageFormat, Rgba};
+use image::ColorType::{Bgra8, Rgba8};
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::tiff::TiffEncoder;
+
+use error::{Error, Result};
+use fractal::color;
+use fractal::definitions::*;
+use fractal::draw;
+use fractal::post;
+use fractal::raw;
+use fractal::render;
+use fractal::space;
+
+//==============================================================================
+// File names
+//==============================================================================
+
+/// Returns the current UTC time as `YYYY-MM-DD_hh-mm-ss`.
+fn timestamp() -> String {
+  let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let (days, secs) = (secs / 86400, secs % 86400);
+
+  // Civil date from the days since 1970-01-01 (Howard Hinnant's algorithm)
+  let z = days as i64 + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+    year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Reserves the path the next export with `export` is written to. The path
+/// never points to a file that existed before.
+pub fn export_path(export : &ExportArgs) -> io::Result<PathBuf> {
+  reserve_path(export, export.format.extension())
+}
+
+/// Reserves the path the next raw data export with `export` is written to.
+pub fn data_path(export : &ExportArgs) -> io::Result<PathBuf> {
+  reserve_path(export, export.data_format.extension())
+}
+
+/// Returns the first path named after `export` with `extension` that does not
+/// point to an existing file. An empty file is created there right away, so
+/// exports running at the same time never pick the same path.
+fn reserve_path(export : &ExportArgs, extension : &str) -> io::Result<PathBuf> {
+  let directory = Path::new(&export.directory);
+  let path_with = |suffix : String| {
+    directory.join(format!("{}_{}.{}", export.name, suffix, extension))
+  };
+  let stamp = timestamp();
+  // The `k`-th path tried, counting from 1
+  let candidate = |k : usize| match export.naming {
+    FileNaming::Numbered => path_with(format!("{:04}", k)),
+    FileNaming::Timestamped if k == 1 => path_with(stamp.clone()),
+    // Exports within the same second get numbered
+    FileNaming::Timestamped => path_with(format!("{}_{}", stamp, k)),
+  };
+
+  let mut k = 1;
+  loop {
+    let path = candidate(k);
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(_) => return Ok(path),
+      Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => k += 1,
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+//==============================================================================
+// Saving
+//==============================================================================
+
+/// Writes the square high precision BGRA image `image` with `pixel_size`
+/// pixels per side to `path` in the format of `export`. PNG and TIFF files get
+/// 16 bits per channel if `export` asks for it, other images are rounded to 8
+/// bits with `dither`. JPEG has no alpha channel, so transparency is dropped
+/// there. PNG files keep the `seed` of a random gradient. Fails if `image`
+/// does not have the size `pixel_size` describes.
+pub fn save_image(
+  path : &Path,
+  image : &HdrImage,
+  pixel_size : usize,
+  export : &ExportArgs,
+  seed : Option<GradientSeed>,
+  dither : Dither)
+-> Result<()> {
+  if image.len() != 4 * pixel_size * pixel_size {
+    return Err(Error::BufferSize{expected : 4 * pixel_size * pixel_size, found : image.len()})
+  }
+  let size = pixel_size as u32;
+
+  if export.format == ExportFormat::Pfm {
+    return write_pfm(&mut BufWriter::new(File::create(path)?), image, pixel_size)
+  }
+  if !export.high_precision() {
+    let image = post::quantize(image, pixel_size, dither);
+    let mut file = BufWriter::new(File::create(path)?);
+    let encoded = match export.format {
+      ExportFormat::Png =>
+        return write_png(file, &color::bgra_to_rgba(&image), size, png::BitDepth::Eight, seed),
+      ExportFormat::Jpeg => {
+        let quality = export.jpeg_quality.max(1).min(100);
+        JpegEncoder::new_with_quality(&mut file, quality).encode(&image, size, size, Bgra8)
+      }
+      ExportFormat::Tiff => TiffEncoder::new(file)
+        .encode(&color::bgra_to_rgba(&image), size, size, Rgba8),
+      ExportFormat::Bmp => BmpEncoder::new(&mut file)
+        .encode(&color::bgra_to_rgba(&image), size, size, Rgba8),
+      // Float maps are always written in high precision
+      ExportFormat::Pfm => unreachable!(),
+    };
+    return Ok(encoded?)
+  }
+
+  let rgba : Vec<u16> = image.chunks(4)
+    .flat_map(|px| vec![px[2], px[1], px[0], px[3]])
+    .map(|c| (65535. * c).round().max(0.).min(65535.) as u16)
+    .collect();
+  if export.format == ExportFormat::Png {
+    // PNG stores 16 bit channels big endian
+    let bytes : Vec<u8> = rgba.iter().flat_map(|c| c.to_be_bytes().to_vec()).collect();
+    let file = BufWriter::new(File::create(path)?);
+    return write_png(file, &bytes, size, png::BitDepth::Sixteen, seed)
+  }
+  let buffer = ImageBuffer::<Rgba<u16>, Vec<u16>>::from_raw(size, size, rgba)
+    .ok_or(Error::BufferSize{expected : 4 * pixel_size * pixel_size, found : image.len()})?;
+  Ok(buffer.save_with_format(path, ImageFormat::Tiff)?)
+}
+
+/// Writes the square RGBA image `data` with `size` pixels per side and `depth`
+/// bits per channel as PNG to `file`. The `seed` of a random gradient is
+/// stored in a text chunk, so the colors of an export can be generated again.
+fn write_png<W : Write>(
+  file : W,
+  data : &[u8],
+  size : u32,
+  depth : png::BitDepth,
+  seed : Option<GradientSeed>)
+-> Result<()> {
+  let mut encoder = png::Encoder::new(file, size, size);
+  encoder.set_color(png::ColorType::RGBA);
+  encoder.set_depth(depth);
+  let mut writer = encoder.write_header().map_err(io::Error::from)?;
+  if let Some(seed) = seed {
+    let text = format!("Gradient seed\0{} {}", seed.seed, seed.scheme);
+    writer.write_chunk(*b"tEXt", text.as_bytes()).map_err(io::Error::from)?;
+  }
+  writer.write_image_data(data).map_err(io::Error::from)?;
+  Ok(())
+}
+
+/// Writes the square high precision image `image` as a portable float map to
+/// `file`. The channels are converted to linear light and alpha is dropped.
+/// Rows are stored from the bottom up in little endian.
+fn write_pfm<W : Write>(file : &mut W, image : &HdrImage, pixel_size : usize) -> Result<()> {
+  write!(file, "PF\n{} {}\n-1.0\n", pixel_size, pixel_size)?;
+  if pixel_size > 0 {
+    for row in image.chunks(4 * pixel_size).rev() {
+      for px in row.chunks(4) {
+        for &c in [px[2], px[1], px[0]].iter() {
+          let linear = space::srgb_to_linear(c.max(0.) as f64) as f32;
+          file.write_all(&linear.to_le_bytes())?;
+        }
+      }
+    }
+  }
+  file.flush()?;
+  Ok(())
+}
+
+/// Colors `fractal` computed with `frac_args` with `img_args` and saves it.
+/// Returns the path of the new file.
+pub fn export_fractal(
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs,
+  export : &ExportArgs)
+-> Result<PathBuf> {
+  let image = render::color_image_hdr(fractal, frac_args, img_args, &mut None)?;
+  let (seed, dither) = (img_args.seed, img_args.post.dither);
+  let path = export_path(export)?;
+  let saved = save_image(&path, &image, frac_args.field.pixel_size, export, seed, dither);
+  discard_failed(&path, saved)
+}
+
+/// Computes the fractal of `frac_args` anew at the resolution of `export`,
+/// colors it with `img_args` and saves it. Returns the path of the new file.
+pub fn export_image(frac_args : &FracArgs, img_args : &ImageArgs, export : &ExportArgs)
+-> Result<PathBuf> {
+  let mut args = *frac_args;
+  args.field.pixel_size = export.pixel_size;
+  draw::check_memory(args.field.pixel_size, img_args)?;
+  let fractal = draw::compute_fractal(args);
+  export_fractal(&fractal, &args, img_args, export)
+}
+
+/// Saves the raw data of `fractal` computed with `frac_args` in the data
+/// format of `export`. Returns the path of the new file.
+pub fn export_data(fractal : &RawFrac, frac_args : &FracArgs, export : &ExportArgs)
+-> Result<PathBuf> {
+  let path = data_path(export)?;
+  let saved = raw::save_raw(&path, fractal, frac_args, export.data_format);
+  discard_failed(&path, saved)
+}
+
+/// Returns `path` if the export `saved` to it succeeded. Otherwise the file is
+/// removed again, as it holds at most part of the export.
+fn discard_failed(path : &Path, saved : Result<()>) -> Result<PathBuf> {
+  match saved {
+    Ok(()) => Ok(path.to_path_buf()),
+    Err(err) => {
+      // The export error is the one worth reporting
+      let _ = fs::remove_file(path);
+      Err(err)
+    }
+  }
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reserved_paths_differ() {
+    let directory = std::env::temp_dir()
+      .join(format!("fractalisator_{}_paths", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for &naming in &[FileNaming::Numbered, FileNaming::Timestamped] {
+      let mut export = ExportArgs::default();
+      export.directory = directory.to_str().unwrap().to_string();
+      export.naming = naming;
+      // Nothing is written to the paths in between
+      let first = export_path(&export).unwrap();
+      let second = export_path(&export).unwrap();
+      assert_ne!(first, second);
+      assert!(first.exists() && second.exists());
+    }
+    fs::remove_dir_all(&directory).unwrap();
+  }
+}
@@ -0,0 +1,199 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::cmp::Ordering;
+
+use image as imagetool;
+
+use fractal::definitions::*;
+use fractal::space;
+
+//==============================================================================
+// Palette extraction
+//==============================================================================
+//
+// The colors of a photo are clustered in Oklab, where distances match the
+// perceived difference of colors. Median cut splits the colors into boxes,
+// the averages of which seed a few rounds of k-means. The clusters are then
+// ordered and every cluster gets a part of the gradient proportional to the
+// number of its pixels.
+
+/// Largest number of pixels used for clustering, larger photos are sampled.
+const MAX_SAMPLES : usize = 65536;
+
+/// Number of k-means rounds refining the median cut clusters.
+const KMEANS_ROUNDS : usize = 8;
+
+/// A cluster of colors in Oklab with the number of its pixels.
+#[derive(Clone, Copy)]
+struct Cluster {
+  lab : [f64; 3],
+  count : usize,
+}
+
+/// Loads the photo at `path` and extracts a gradient of `colors` colors from
+/// it, see `extract_palette`.
+pub fn load_photo_palette(path : &str, colors : usize, order : PaletteOrder)
+-> imagetool::ImageResult<Gradient> {
+  let photo = imagetool::open(path)?.to_bgra8();
+  Ok(extract_palette(photo.as_raw(), colors, order))
+}
+
+/// Extracts a gradient of at most `colors` (at least 2) colors from the BGRA
+/// pixels `bgra`. Mostly transparent pixels are ignored. The colors are put in
+/// `order` and each color takes a part of the gradient proportional to how
+/// much of the photo it covers.
+pub fn extract_palette(bgra : &[u8], colors : usize, order : PaletteOrder) -> Gradient {
+  let samples = sample_colors(bgra);
+  let clusters =
+    if samples.is_empty() {
+      vec!(Cluster{lab : [0.; 3], count : 1})
+    } else {
+      let seeds = median_cut(samples.clone(), colors.max(2));
+      kmeans(&samples, seeds)
+    };
+  palette_gradient(clusters, order)
+}
+
+/// Returns the Oklab colors of at most `MAX_SAMPLES` evenly spread opaque
+/// pixels of `bgra`.
+fn sample_colors(bgra : &[u8]) -> Vec<[f64; 3]> {
+  let pixels = bgra.len() / 4;
+  let stride = (pixels / MAX_SAMPLES).max(1);
+
+  (0..pixels).step_by(stride)
+    .map(|i| (bgra[4*i], bgra[4*i + 1], bgra[4*i + 2], bgra[4*i + 3]))
+    .filter(|&(_, _, _, a)| a >= 128)
+    .map(|(b, g, r, _)| {
+      let lab = space::to_space(ColorSpace::Oklab, (b, g, r, 255));
+      [lab[0], lab[1], lab[2]]
+    })
+    .collect()
+}
+
+/// Returns the average of `colors`, which must not be empty.
+fn average(colors : &[[f64; 3]]) -> [f64; 3] {
+  let mut sum = [0.; 3];
+  for color in colors {
+    for k in 0..3 {
+      sum[k] += color[k];
+    }
+  }
+  let n = colors.len() as f64;
+  [sum[0] / n, sum[1] / n, sum[2] / n]
+}
+
+/// Splits `colors` into at most `count` boxes by repeatedly cutting the box
+/// with the widest channel range at its median, and returns their averages.
+fn median_cut(colors : Vec<[f64; 3]>, count : usize) -> Vec<Cluster> {
+  let range = |colors : &[[f64; 3]], k : usize| {
+    let (min, max) = colors.iter().fold((std::f64::MAX, std::f64::MIN),
+      |(min, max), color| (min.min(color[k]), max.max(color[k])));
+    max - min
+  };
+  // The channel with the widest range and the range itself
+  let widest = |colors : &[[f64; 3]]| (0..3)
+    .map(|k| (k, range(colors, k)))
+    .fold((0, 0.), |best, next| if next.1 > best.1 { next } else { best });
+
+  let mut boxes = vec!(colors);
+  while boxes.len() < count {
+    let candidate = boxes.iter().enumerate()
+      .filter(|(_, colors)| colors.len() > 1)
+      .map(|(i, colors)| (i, widest(colors)))
+      .max_by(|a, b| (a.1).1.partial_cmp(&(b.1).1).unwrap_or(Ordering::Equal));
+    let (i, (k, width)) = match candidate {
+      Some(candidate) => candidate,
+      None => break,
+    };
+    if width <= 0. {
+      break
+    }
+
+    let mut colors = boxes.swap_remove(i);
+    colors.sort_by(|a, b| a[k].partial_cmp(&b[k]).unwrap_or(Ordering::Equal));
+    let upper = colors.split_off(colors.len() / 2);
+    boxes.push(colors);
+    boxes.push(upper);
+  }
+
+  boxes.iter()
+    .map(|colors| Cluster{lab : average(colors), count : colors.len()})
+    .collect()
+}
+
+/// Squared distance between Oklab colors.
+fn distance(a : &[f64; 3], b : &[f64; 3]) -> f64 {
+  (0..3).map(|k| (a[k] - b[k]) * (a[k] - b[k])).sum()
+}
+
+/// Refines `clusters` by assigning each color to the nearest cluster and
+/// moving clusters to the average of their colors. Empty clusters are
+/// dropped.
+fn kmeans(colors : &[[f64; 3]], mut clusters : Vec<Cluster>) -> Vec<Cluster> {
+  for _ in 0..KMEANS_ROUNDS {
+    let mut sums = vec!([0.; 3]; clusters.len());
+    let mut counts = vec!(0; clusters.len());
+    for color in colors {
+      let nearest = (0..clusters.len())
+        .min_by(|&i, &j| distance(color, &clusters[i].lab)
+          .partial_cmp(&distance(color, &clusters[j].lab))
+          .unwrap_or(Ordering::Equal))
+        .unwrap();
+      for k in 0..3 {
+        sums[nearest][k] += color[k];
+      }
+      counts[nearest] += 1;
+    }
+
+    clusters = sums.iter().zip(counts.iter())
+      .filter(|&(_, &count)| count > 0)
+      .map(|(sum, &count)| {
+        let n = count as f64;
+        Cluster{lab : [sum[0] / n, sum[1] / n, sum[2] / n], count : count}
+      })
+      .collect();
+  }
+  clusters
+}
+
+/// Orders `clusters` and spreads them over a gradient. Each cluster sits in
+/// the middle of a part of the gradient proportional to its size, the first
+/// and last cluster are stretched to the ends.
+fn palette_gradient(mut clusters : Vec<Cluster>, order : PaletteOrder) -> Gradient {
+  let to_bgra = |lab : [f64; 3]| space::from_space(
+    ColorSpace::Oklab, [lab[0], lab[1], lab[2], 255.]);
+  let key = |cluster : &Cluster| match order {
+    PaletteOrder::Luminance => cluster.lab[0],
+    PaletteOrder::Hue => space::to_space(ColorSpace::Oklch, to_bgra(cluster.lab))[0],
+  };
+  clusters.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal));
+
+  let total : usize = clusters.iter().map(|cluster| cluster.count).sum();
+  let mut before = 0;
+  let centers : Vec<f64> = clusters.iter()
+    .map(|cluster| {
+      let center = (before as f64 + cluster.count as f64 / 2.) / total as f64;
+      before += cluster.count;
+      center
+    })
+    .collect();
+
+  let last = clusters.len() - 1;
+  let (first_center, last_center) = (centers[0], centers[last]);
+  let peaks = (1..last)
+    .map(|i| ColorPeak{
+      bgra : to_bgra(clusters[i].lab),
+      at : (centers[i] - first_center) / (last_center - first_center),
+    })
+    .collect();
+
+  Gradient {
+    start_color : to_bgra(clusters[0].lab),
+    peaks : peaks,
+    end_color : to_bgra(clusters[last].lab),
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend{space : ColorSpace::Oklab, ..GradientBlend::default()},
+  }
+}
@@ -0,0 +1,107 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rayon::prelude::*;
+
+use fractal::algorithms::{ColorOutput, ColoringContext};
+use fractal::color;
+use fractal::definitions::*;
+
+//==============================================================================
+// Layer Coloring
+//==============================================================================
+
+/// Colors `fractal` with the algorithm and gradient of `layer`, with channels
+/// on the scale [0, 1]. Points outside of the layer mask are left
+/// transparent.
+pub fn color_layer(
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs,
+  layer : &Layer)
+-> HdrImage {
+  let gradient_fun = color::gradient_function_hdr(layer.gradient.clone());
+  let mut colored = vec![0.; 4 * fractal.len()];
+
+  colored.par_chunks_mut(4).zip(fractal.par_iter())
+    .for_each(|(pixel, point)| {
+      let exterior = point.n < frac_args.steps;
+      let masked = match layer.mask {
+        LayerMask::Everywhere => false,
+        LayerMask::Exterior => !exterior,
+        LayerMask::Interior => exterior,
+      };
+      if masked {
+        return
+      }
+      let ctx = ColoringContext{
+        frac_args : frac_args,
+        img_args : img_args,
+        slot_gradient : &layer.gradient,
+        gradient : &gradient_fun,
+      };
+      let bgra = match layer.algorithm.0.color(point, &ctx) {
+        ColorOutput::Index(x) => gradient_fun(x.max(0.).min(1.)),
+        ColorOutput::Color(bgra) => bgra,
+      };
+      for c in 0..4 {
+        pixel[c] = bgra[c] as f32;
+      }
+    });
+
+  colored
+}
+
+//==============================================================================
+// Compositing
+//==============================================================================
+
+/// Blends the channel `top` of a layer over the channel `bottom` beneath it,
+/// both on the scale [0, 1].
+fn blend_channel(mode : BlendMode, bottom : f64, top : f64) -> f64 {
+  match mode {
+    BlendMode::Normal => top,
+    BlendMode::Multiply => bottom * top,
+    BlendMode::Screen => 1. - (1. - bottom) * (1. - top),
+    BlendMode::Overlay =>
+      if bottom < 0.5 {
+        2. * bottom * top
+      } else {
+        1. - 2. * (1. - bottom) * (1. - top)
+      },
+    BlendMode::Add => (bottom + top).min(1.),
+    BlendMode::Difference => (bottom - top).abs(),
+  }
+}
+
+/// Composites the colors `layer_image` of `layer` over the BGRA image `base`.
+pub fn composite(base : &mut HdrImage, layer_image : &HdrImage, layer : &Layer) {
+  let opacity = layer.opacity.max(0.).min(1.);
+
+  base.par_chunks_mut(4).zip(layer_image.par_chunks(4)).for_each(|(pixel, top)| {
+    let coverage = opacity * top[3] as f64;
+    if coverage <= 0. {
+      return
+    }
+    for c in 0..3 {
+      let bottom = pixel[c] as f64;
+      let blended = blend_channel(layer.blend, bottom, top[c] as f64);
+      pixel[c] = (bottom + (blended - bottom) * coverage) as f32;
+    }
+    let alpha = pixel[3] as f64;
+    pixel[3] = (alpha + coverage * (1. - alpha)).min(1.) as f32;
+  });
+}
+
+/// Colors the layers of `img_args` and composites them over `base` from the
+/// bottom up.
+pub fn composite_layers(
+  base : &mut HdrImage,
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs) {
+  for layer in img_args.layers.iter() {
+    let layer_image = color_layer(fractal, frac_args, img_args, layer);
+    composite(base, &layer_image, layer);
+  }
+}
@@ -0,0 +1,125 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::fs;
+use std::path::Path;
+
+use fractal::definitions::*;
+use fractal::palette;
+use fractal::palette::PaletteError;
+
+//==============================================================================
+// Preset library
+//==============================================================================
+
+/// Directory from which gradient presets are loaded and into which new
+/// presets are saved.
+pub static PRESET_DIRECTORY : &str = "gradients";
+
+/// Name of the preset used for the default gradient.
+pub static DEFAULT_PRESET : &str = "Gaia";
+
+/// A named gradient.
+#[derive(Clone)]
+pub struct Preset {
+  pub name : String,
+  pub gradient : Gradient,
+}
+
+/// Named gradient presets. The built-in presets come first, followed by the
+/// presets loaded from the preset directory in alphabetical order. Files that
+/// could not be loaded are listed in `errors`.
+pub struct PresetLibrary {
+  pub directory : String,
+  pub presets : Vec<Preset>,
+  pub errors : Vec<String>,
+}
+
+/// Returns the presets that ship with the program.
+pub fn built_in_presets() -> Vec<Preset> {
+  vec!(
+    Preset{name : "Azul".to_string(), gradient : Gradient::azul()},
+    Preset{name : "Svarog".to_string(), gradient : Gradient::svarog()},
+    Preset{name : "Emperor".to_string(), gradient : Gradient::emperor()},
+    Preset{name : "Gaia".to_string(), gradient : Gradient::gaia()},
+  )
+}
+
+impl PresetLibrary {
+
+  /// Loads the built-in presets and every palette in `directory` that
+  /// `palette::load_palette` understands. A preset is named after its file,
+  /// files named like a built-in preset replace it. A missing directory
+  /// leaves only the built-in presets.
+  pub fn load(directory : &str) -> PresetLibrary {
+    let mut library = PresetLibrary {
+      directory : directory.to_string(),
+      presets : built_in_presets(),
+      errors : Vec::new(),
+    };
+
+    let mut paths : Vec<_> = match fs::read_dir(directory) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect(),
+      Err(_) => return library,
+    };
+    paths.sort();
+
+    for path in paths {
+      let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(name) => name.to_string(),
+        None => continue,
+      };
+      match palette::load_palette(&path.to_string_lossy()) {
+        Ok(gradient) => library.insert(name, gradient),
+        Err(PaletteError::UnknownFormat(_)) => {}
+        Err(err) => library.errors.push(format!("{}: {}", path.display(), err)),
+      }
+    }
+    library
+  }
+
+  /// Adds the preset `name`, replacing a preset of the same name.
+  pub fn insert(&mut self, name : String, gradient : Gradient) {
+    match self.presets.iter_mut().find(|preset| preset.name == name) {
+      Some(preset) => preset.gradient = gradient,
+      None => self.presets.push(Preset{name : name, gradient : gradient}),
+    }
+  }
+
+  /// Returns the gradient of the preset `name`.
+  pub fn get(&self, name : &str) -> Option<&Gradient> {
+    self.presets.iter()
+      .find(|preset| preset.name == name)
+      .map(|preset| &preset.gradient)
+  }
+
+  /// Returns the names of the presets containing `query`, ignoring case.
+  pub fn search(&self, query : &str) -> Vec<String> {
+    let query = query.to_lowercase();
+    self.presets.iter()
+      .filter(|preset| preset.name.to_lowercase().contains(&query))
+      .map(|preset| preset.name.clone())
+      .collect()
+  }
+
+  /// Saves `gradient` as the preset `name` into the preset directory and adds
+  /// it to the library.
+  pub fn save(&mut self, name : &str, gradient : &Gradient) -> Result<(), PaletteError> {
+    let name = name.trim();
+    let valid = !name.is_empty()
+      && !name.contains(|c| c == '/' || c == '\\' || c == '.');
+    if !valid {
+      return Err(PaletteError::InvalidName(name.to_string()))
+    }
+
+    fs::create_dir_all(&self.directory)?;
+    let path = Path::new(&self.directory).join(format!("{}.fgr", name));
+    palette::save_palette(&path.to_string_lossy(), gradient)?;
+    self.insert(name.to_string(), gradient.clone());
+    Ok(())
+  }
+}
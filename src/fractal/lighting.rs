@@ -0,0 +1,110 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rayon::prelude::*;
+
+use fractal::algorithms;
+use fractal::definitions::*;
+
+//==============================================================================
+// Surface
+//==============================================================================
+//
+// The fractal is lit as a surface whose height is taken from the fractal
+// points. Normals come from central differences of the heights of the
+// neighbouring pixels. Points that did not escape are not lit, and count as
+// being level with their neighbours.
+
+/// Returns the height of `point`, or `None` if it did not escape.
+fn height(point : &FracPoint, frac_args : &FracArgs, source : HeightSource) -> Option<f64> {
+  if point.n >= frac_args.steps {
+    return None
+  }
+  let h = match source {
+    HeightSource::SmoothIteration => algorithms::smooth_iterations(point).max(1.).ln(),
+    HeightSource::DistanceEstimate => {
+      // Distance in pixels, so that the height does not depend on the zoom
+      let spacing = 2. * frac_args.field.radius / frac_args.field.pixel_size as f64;
+      (point.dist / spacing).max(1e-3).ln()
+    }
+  };
+  if h.is_finite() { Some(h) } else { None }
+}
+
+/// Normalizes the vector `v`.
+fn normalize(v : [f64; 3]) -> [f64; 3] {
+  let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+  [v[0] / length, v[1] / length, v[2] / length]
+}
+
+/// Dot product of `a` and `b`.
+fn dot(a : [f64; 3], b : [f64; 3]) -> f64 {
+  a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+//==============================================================================
+// Shading
+//==============================================================================
+
+/// Returns for every point the factor its color is multiplied by and the
+/// highlight added to it, or `None` for points that are not lit.
+pub fn shading(fractal : &RawFrac, frac_args : &FracArgs, lighting : &Lighting)
+-> Vec<Option<(f64, f64)>> {
+  let size = frac_args.field.pixel_size;
+  let heights : Vec<Option<f64>> = fractal.par_iter()
+    .map(|point| height(point, frac_args, lighting.source))
+    .collect();
+
+  let (azimuth, elevation) = (lighting.azimuth.to_radians(), lighting.elevation.to_radians());
+  let light = [
+    elevation.cos() * azimuth.cos(),
+    elevation.cos() * azimuth.sin(),
+    elevation.sin()];
+  // The viewer looks straight down on the image
+  let halfway = normalize([light[0], light[1], light[2] + 1.]);
+
+  (0..fractal.len()).into_par_iter()
+    .map(|k| {
+      let h = heights[k]?;
+      let (row, col) = (k / size, k % size);
+      let at = |row : usize, col : usize| heights[row * size + col].unwrap_or(h);
+      let dx = (at(row, (col + 1).min(size - 1)) - at(row, col.saturating_sub(1))) / 2.;
+      let dy = (at((row + 1).min(size - 1), col) - at(row.saturating_sub(1), col)) / 2.;
+      let normal = normalize([-lighting.height * dx, -lighting.height * dy, 1.]);
+
+      let diffuse = dot(normal, light).max(0.);
+      let factor = lighting.ambient + (1. - lighting.ambient) * diffuse;
+      let highlight = match lighting.model {
+        LightingModel::BlinnPhong if diffuse > 0. =>
+          lighting.specular * dot(normal, halfway).max(0.).powf(lighting.shininess),
+        _ => 0.,
+      };
+      Some((factor, highlight))
+    })
+    .collect()
+}
+
+/// Applies `shading` to the BGRA image `colored`, keeping the alpha.
+/// Highlights are not clipped.
+pub fn apply_shading(colored : &mut HdrImage, shading : &[Option<(f64, f64)>]) {
+  colored.par_chunks_mut(4).zip(shading.par_iter()).for_each(|(pixel, shade)| {
+    if let Some((factor, highlight)) = *shade {
+      for c in pixel[..3].iter_mut() {
+        *c = (*c as f64 * factor + highlight).max(0.) as f32;
+      }
+    }
+  });
+}
+
+/// Lights the BGRA image `colored` of `fractal` as described by `lighting`.
+/// Does nothing if the lighting is off.
+pub fn light_image(
+  colored : &mut HdrImage,
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  lighting : &Lighting) {
+  if lighting.model == LightingModel::Off {
+    return
+  }
+  apply_shading(colored, &shading(fractal, frac_args, lighting));
+}
@@ -1,3 +1,18 @@
+pub mod algorithms;
+pub mod alpha;
 pub mod color;
+pub mod cycle;
 pub mod definitions;
-pub mod draw;
\ No newline at end of file
+pub mod draw;
+pub mod export;
+pub mod extract;
+pub mod layers;
+pub mod library;
+pub mod lighting;
+pub mod palette;
+pub mod post;
+pub mod random;
+pub mod raw;
+pub mod render;
+pub mod space;
+pub mod texture;
@@ -0,0 +1,547 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::fs;
+use std::path::Path;
+use std::fmt::Write;
+
+use fractal::definitions::*;
+
+//==============================================================================
+// Errors
+//==============================================================================
+
+/// Describes why a palette file could not be imported or saved. Format errors
+/// report the line number (starting with 1) at which the problem was found.
+#[derive(Debug)]
+pub enum PaletteError {
+  Io(std::io::Error),
+  UnknownFormat(String),
+  InvalidName(String),
+  Format { line : usize, message : String },
+}
+
+impl std::fmt::Display for PaletteError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      PaletteError::Io(err) => write!(f, "could not access palette file: {}", err),
+      PaletteError::UnknownFormat(ext) =>
+        write!(f, "unknown palette format '{}'", ext),
+      PaletteError::InvalidName(name) =>
+        write!(f, "'{}' is not a valid preset name", name),
+      PaletteError::Format{line, message} =>
+        write!(f, "malformed palette at line {}: {}", line, message),
+    }
+  }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+  fn from(err : std::io::Error) -> Self { PaletteError::Io(err) }
+}
+
+/// Creates a format error at `line` (counted from 0) with `message`.
+fn format_error(line : usize, message : &str) -> PaletteError {
+  PaletteError::Format{line : line + 1, message : message.to_string()}
+}
+
+//==============================================================================
+// Loading
+//==============================================================================
+
+/// Loads the palette at `path` as a gradient. The format is picked by the
+/// extension: GIMP gradients `.ggr` and palettes `.gpl`, Fractint maps `.map`,
+/// Ultra Fractal gradients `.ugr` and our own gradients `.fgr`.
+pub fn load_palette(path : &str) -> Result<Gradient, PaletteError> {
+  let extension = Path::new(path).extension()
+    .and_then(|ext| ext.to_str())
+    .unwrap_or("")
+    .to_lowercase();
+  let text = fs::read_to_string(path)?;
+
+  match extension.as_str() {
+    "ggr" => parse_ggr(&text),
+    "gpl" => parse_gpl(&text),
+    "map" => parse_map(&text),
+    "ugr" => parse_ugr(&text),
+    "fgr" => parse_fgr(&text),
+    _ => Err(PaletteError::UnknownFormat(extension)),
+  }
+}
+
+/// Saves `gradient` to `path` in our own `.fgr` format.
+pub fn save_palette(path : &str, gradient : &Gradient) -> Result<(), PaletteError> {
+  fs::write(path, write_fgr(gradient))?;
+  Ok(())
+}
+
+//==============================================================================
+// Parsers
+//==============================================================================
+
+/// Converts a color channel from [0, 1] to `u8`.
+fn unit_to_u8(x : f64) -> u8 {
+  (x * 255.).round().max(0.).min(255.) as u8
+}
+
+/// Creates a gradient that goes through `colors` at equal distances.
+fn evenly_spaced(colors : &[(u8, u8, u8, u8)]) -> Gradient {
+  let last = colors.len() - 1;
+  let peaks = (1..last)
+    .map(|i| ColorPeak{bgra : colors[i], at : i as f64 / last as f64})
+    .collect();
+
+  Gradient {
+    start_color : colors[0],
+    peaks : peaks,
+    end_color : colors[last],
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend::default(),
+  }
+}
+
+/// Parses a single finite number, reporting errors at `line`.
+fn parse_number(value : &str, line : usize) -> Result<f64, PaletteError> {
+  match value.parse::<f64>() {
+    Ok(x) if x.is_finite() => Ok(x),
+    _ => Err(format_error(line, &format!("'{}' is not a number", value))),
+  }
+}
+
+/// Parses a line of whitespace separated numbers, reporting errors at `line`.
+fn parse_numbers(text : &str, line : usize) -> Result<Vec<f64>, PaletteError> {
+  text.split_whitespace()
+    .map(|word| parse_number(word, line))
+    .collect()
+}
+
+/// Parses a GIMP gradient. Every segment contributes its end points and its
+/// midpoint as peaks, segments are blended linearly regardless of their blend
+/// and coloring type.
+pub fn parse_ggr(text : &str) -> Result<Gradient, PaletteError> {
+  let mut lines = text.lines().enumerate()
+    .filter(|(_, line)| !line.trim().is_empty());
+
+  match lines.next() {
+    Some((_, line)) if line.trim() == "GIMP Gradient" => {}
+    Some((i, _)) => return Err(format_error(i, "missing 'GIMP Gradient' header")),
+    None => return Err(format_error(0, "empty file")),
+  }
+  let mut next = lines.next();
+  if let Some((_, line)) = next {
+    if line.starts_with("Name:") {
+      next = lines.next();
+    }
+  }
+  let count = match next {
+    Some((i, line)) => line.trim().parse::<usize>()
+      .map_err(|_| format_error(i, "expected the number of segments"))?,
+    None => return Err(format_error(0, "missing number of segments")),
+  };
+  if count == 0 {
+    return Err(format_error(0, "the gradient has no segments"))
+  }
+
+  let mut colors = Vec::new();
+  for _ in 0..count {
+    let (i, line) = lines.next()
+      .ok_or_else(|| format_error(text.lines().count(), "missing segment"))?;
+    let values = parse_numbers(line, i)?;
+    if values.len() < 11 {
+      return Err(format_error(i, "a segment needs at least 11 values"))
+    }
+    if !values[..3].iter().all(|at| (0. ..= 1.).contains(at)) {
+      return Err(format_error(i, "segment positions must be between 0 and 1"))
+    }
+    let color = |k : usize| (
+      unit_to_u8(values[k + 2]), unit_to_u8(values[k + 1]),
+      unit_to_u8(values[k]), unit_to_u8(values[k + 3]));
+    let (left, right) = (color(3), color(7));
+    let middle = (
+      ((left.0 as u16 + right.0 as u16) / 2) as u8,
+      ((left.1 as u16 + right.1 as u16) / 2) as u8,
+      ((left.2 as u16 + right.2 as u16) / 2) as u8,
+      ((left.3 as u16 + right.3 as u16) / 2) as u8);
+    colors.push(ColorPeak{bgra : left, at : values[0]});
+    colors.push(ColorPeak{bgra : middle, at : values[1]});
+    colors.push(ColorPeak{bgra : right, at : values[2]});
+  }
+
+  let start_color = colors[0].bgra;
+  let end_color = colors[colors.len() - 1].bgra;
+  let peaks = colors[1..colors.len() - 1].to_vec();
+
+  Ok(Gradient {
+    start_color : start_color,
+    peaks : peaks,
+    end_color : end_color,
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend::default(),
+  })
+}
+
+/// Parses a GIMP palette. The colors are spread evenly over the gradient.
+pub fn parse_gpl(text : &str) -> Result<Gradient, PaletteError> {
+  let mut colors = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if i == 0 {
+      if line != "GIMP Palette" {
+        return Err(format_error(i, "missing 'GIMP Palette' header"))
+      }
+      continue
+    }
+    if line.is_empty() || line.starts_with('#') || line.contains(':') {
+      continue
+    }
+    // The color may be followed by its name
+    let words : Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 3 {
+      return Err(format_error(i, "a color needs red, green and blue values"))
+    }
+    colors.push(parse_rgb(&words[..3], i)?);
+  }
+
+  if colors.len() < 2 {
+    return Err(format_error(0, "a palette needs at least two colors"))
+  }
+  Ok(evenly_spaced(&colors))
+}
+
+/// Parses a Fractint map with a color on each line. The colors are spread
+/// evenly over the gradient.
+pub fn parse_map(text : &str) -> Result<Gradient, PaletteError> {
+  let mut colors = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let words : Vec<&str> = line.split_whitespace().collect();
+    if words.is_empty() {
+      continue
+    }
+    if words.len() < 3 {
+      return Err(format_error(i, "a color needs red, green and blue values"))
+    }
+    colors.push(parse_rgb(&words[..3], i)?);
+  }
+
+  if colors.len() < 2 {
+    return Err(format_error(0, "a map needs at least two colors"))
+  }
+  Ok(evenly_spaced(&colors))
+}
+
+/// Parses red, green and blue values between 0 and 255 from `words`.
+fn parse_rgb(words : &[&str], line : usize)
+-> Result<(u8, u8, u8, u8), PaletteError> {
+  let mut rgb = [0; 3];
+  for k in 0..3 {
+    rgb[k] = words[k].parse::<u8>().map_err(|_| format_error(line,
+      &format!("'{}' is not a color value between 0 and 255", words[k])))?;
+  }
+  Ok((rgb[2], rgb[1], rgb[0], 255))
+}
+
+/// Number of positions in an Ultra Fractal gradient.
+const UGR_POSITIONS : f64 = 400.;
+
+/// Parses the first gradient of an Ultra Fractal gradient file. Ultra Fractal
+/// gradients are cyclic, so the gradient ends with the color it starts with.
+/// Smooth gradients use Catmull-Rom curves. Opacity is ignored.
+pub fn parse_ugr(text : &str) -> Result<Gradient, PaletteError> {
+  let mut in_gradient = false;
+  let mut smooth = false;
+  let mut colors : Vec<ColorPeak> = Vec::new();
+
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.starts_with("gradient:") {
+      in_gradient = true;
+      continue
+    }
+    if !in_gradient {
+      continue
+    }
+    // The gradient section ends with the next section or gradient
+    if line.starts_with("opacity:") || line.starts_with('}') {
+      break
+    }
+
+    let mut index = None;
+    let mut color = None;
+    for pair in line.split_whitespace() {
+      let mut parts = pair.splitn(2, '=');
+      let (key, value) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+      match key {
+        "smooth" => smooth = value == "yes",
+        "index" => index = Some(value.parse::<i64>()
+          .map_err(|_| format_error(i, &format!("bad index '{}'", value)))?),
+        "color" => color = Some(value.parse::<u32>()
+          .map_err(|_| format_error(i, &format!("bad color '{}'", value)))?),
+        _ => {}
+      }
+    }
+
+    match (index, color) {
+      (Some(index), Some(color)) => {
+        // Colors are stored as `b * 65536 + g * 256 + r`
+        let bgra = (
+          ((color >> 16) & 255) as u8, ((color >> 8) & 255) as u8,
+          (color & 255) as u8, 255);
+        let at = (index as f64 / UGR_POSITIONS).max(0.).min(1.);
+        colors.push(ColorPeak{bgra : bgra, at : at});
+      }
+      (None, None) => {}
+      _ => return Err(format_error(i, "expected both an index and a color")),
+    }
+  }
+
+  if colors.is_empty() {
+    return Err(format_error(0, "no gradient colors found"))
+  }
+  colors.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
+  let first = colors[0].bgra;
+
+  Ok(Gradient {
+    start_color : first,
+    peaks : colors,
+    end_color : first,
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend{
+      curve : if smooth { GradientCurve::CatmullRom } else { GradientCurve::Linear },
+      ..GradientBlend::default()
+    },
+  })
+}
+
+//==============================================================================
+// Fractalisator gradients
+//==============================================================================
+//
+// Our own format keeps everything a `Gradient` describes. After the header
+// line `Fractalisator Gradient` every line holds a `key: value` pair. Colors
+// are written as `red green blue alpha` and peaks as `at red green blue
+// alpha`. Settings are written with their displayed names, for example
+//
+//   Fractalisator Gradient
+//   smooth: yes
+//   transform: Linear index
+//   density: 1
+//   repeat: 1
+//   offset: 0
+//   wrap: Clamp
+//   space: sRGB
+//   hue path: Shorter hue
+//   curve: Linear curve
+//   start: 0 0 0 255
+//   peak: 0.3 30 120 220 255
+//   end: 0 0 0 255
+
+/// Header line of our own gradient format.
+const FGR_HEADER : &str = "Fractalisator Gradient";
+
+/// Writes `gradient` in our own `.fgr` format.
+pub fn write_fgr(gradient : &Gradient) -> String {
+  let color = |(b, g, r, a) : (u8, u8, u8, u8)| format!("{} {} {} {}", r, g, b, a);
+  let mapping = gradient.mapping;
+  let blend = gradient.blend;
+
+  let mut text = String::new();
+  // Writing to a `String` cannot fail
+  writeln!(text, "{}", FGR_HEADER).unwrap();
+  writeln!(text, "smooth: {}", if gradient.smooth { "yes" } else { "no" }).unwrap();
+  writeln!(text, "transform: {}", mapping.transform).unwrap();
+  writeln!(text, "density: {}", mapping.density).unwrap();
+  writeln!(text, "repeat: {}", mapping.repeat).unwrap();
+  writeln!(text, "offset: {}", mapping.offset).unwrap();
+  writeln!(text, "wrap: {}", mapping.wrap).unwrap();
+  writeln!(text, "space: {}", blend.space).unwrap();
+  writeln!(text, "hue path: {}", blend.hue_path).unwrap();
+  writeln!(text, "curve: {}", blend.curve).unwrap();
+  writeln!(text, "start: {}", color(gradient.start_color)).unwrap();
+  for peak in &gradient.peaks {
+    writeln!(text, "peak: {} {}", peak.at, color(peak.bgra)).unwrap();
+  }
+  writeln!(text, "end: {}", color(gradient.end_color)).unwrap();
+  text
+}
+
+/// Finds the option of `all` displayed as `value`.
+fn parse_choice<T : Copy + ToString>(all : &[T], value : &str, line : usize)
+-> Result<T, PaletteError> {
+  all.iter().cloned().find(|option| option.to_string() == value)
+    .ok_or_else(|| format_error(line, &format!("unknown option '{}'", value)))
+}
+
+/// Parses a color written as `red green blue alpha`.
+fn parse_rgba(words : &[&str], line : usize)
+-> Result<(u8, u8, u8, u8), PaletteError> {
+  if words.len() != 4 {
+    return Err(format_error(line, "a color needs red, green, blue and alpha values"))
+  }
+  let (b, g, r, _) = parse_rgb(&words[..3], line)?;
+  let a = words[3].parse::<u8>().map_err(|_| format_error(line,
+    &format!("'{}' is not a color value between 0 and 255", words[3])))?;
+  Ok((b, g, r, a))
+}
+
+/// Parses a gradient in our own `.fgr` format. Settings that are left out
+/// keep their defaults, the start and end colors are required.
+pub fn parse_fgr(text : &str) -> Result<Gradient, PaletteError> {
+  let mut gradient = Gradient {
+    start_color : (0, 0, 0, 255),
+    peaks : Vec::new(),
+    end_color : (0, 0, 0, 255),
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend::default(),
+  };
+  let (mut has_start, mut has_end) = (false, false);
+
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if i == 0 {
+      if line != FGR_HEADER {
+        return Err(format_error(i, &format!("missing '{}' header", FGR_HEADER)))
+      }
+      continue
+    }
+    if line.is_empty() {
+      continue
+    }
+
+    let mut parts = line.splitn(2, ':');
+    let key = parts.next().unwrap().trim();
+    let value = parts.next()
+      .ok_or_else(|| format_error(i, "expected 'key: value'"))?
+      .trim();
+    let words : Vec<&str> = value.split_whitespace().collect();
+
+    match key {
+      "smooth" => gradient.smooth = value == "yes",
+      "transform" =>
+        gradient.mapping.transform = parse_choice(ALL_INDEX_TRANSFORMS, value, i)?,
+      "density" => gradient.mapping.density = parse_number(value, i)?,
+      "repeat" => gradient.mapping.repeat = parse_number(value, i)?,
+      "offset" => gradient.mapping.offset = parse_number(value, i)?,
+      "wrap" => gradient.mapping.wrap = parse_choice(ALL_GRADIENT_WRAPS, value, i)?,
+      "space" => gradient.blend.space = parse_choice(ALL_COLOR_SPACES, value, i)?,
+      "hue path" => gradient.blend.hue_path = parse_choice(ALL_HUE_PATHS, value, i)?,
+      "curve" => gradient.blend.curve = parse_choice(ALL_GRADIENT_CURVES, value, i)?,
+      "start" => {
+        gradient.start_color = parse_rgba(&words, i)?;
+        has_start = true
+      }
+      "peak" => {
+        if words.is_empty() {
+          return Err(format_error(i, "a peak needs a position and a color"))
+        }
+        let at = parse_number(words[0], i)?;
+        if !(0. ..= 1.).contains(&at) {
+          return Err(format_error(i, "a peak position must be between 0 and 1"))
+        }
+        gradient.peaks.push(ColorPeak{bgra : parse_rgba(&words[1..], i)?, at : at})
+      }
+      "end" => {
+        gradient.end_color = parse_rgba(&words, i)?;
+        has_end = true
+      }
+      _ => return Err(format_error(i, &format!("unknown key '{}'", key))),
+    }
+  }
+
+  if !has_start || !has_end {
+    return Err(format_error(0, "the start and end colors are required"))
+  }
+  Ok(gradient)
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GGR : &str = "GIMP Gradient\nName: Test\n2\n\
+    0 0.25 0.5 1 0 0 1 0 1 0 1 0 0\n\
+    0.5 0.75 1 0 1 0 1 0 0 1 1 0 0\n";
+
+  /// Returns the line a format error was reported at.
+  fn error_line(result : Result<Gradient, PaletteError>) -> usize {
+    match result {
+      Err(PaletteError::Format{line, ..}) => line,
+      Err(err) => panic!("expected a format error, found {}", err),
+      Ok(_) => panic!("expected a format error"),
+    }
+  }
+
+  #[test]
+  fn ggr_segments_become_peaks() {
+    let gradient = parse_ggr(GGR).unwrap();
+    assert_eq!(gradient.start_color, (0, 0, 255, 255));
+    assert_eq!(gradient.end_color, (255, 0, 0, 255));
+    assert_eq!(gradient.peaks.len(), 4);
+    assert_eq!(gradient.peaks[1].at, 0.5);
+  }
+
+  #[test]
+  fn ggr_rejects_non_finite_numbers() {
+    let text = GGR.replace("0.25", "NaN");
+    assert_eq!(error_line(parse_ggr(&text)), 4);
+    let text = GGR.replace("0.75", "inf");
+    assert_eq!(error_line(parse_ggr(&text)), 5);
+  }
+
+  #[test]
+  fn ggr_rejects_positions_outside_the_gradient() {
+    let text = GGR.replace("0.5 0.75 1", "0.5 0.75 1.5");
+    assert_eq!(error_line(parse_ggr(&text)), 5);
+    let text = GGR.replace("0 0.25 0.5", "-0.5 0.25 0.5");
+    assert_eq!(error_line(parse_ggr(&text)), 4);
+  }
+
+  #[test]
+  fn ggr_rejects_missing_segments() {
+    let text = GGR.replace("\n2\n", "\n3\n");
+    assert!(error_line(parse_ggr(&text)) > 0);
+    assert_eq!(error_line(parse_ggr("GIMP Gradient\n0\n")), 1);
+    assert_eq!(error_line(parse_ggr("GIMP Palette\n")), 1);
+  }
+
+  #[test]
+  fn fgr_round_trips() {
+    let mut gradient = Gradient::default();
+    gradient.mapping.repeat = 3.;
+    gradient.blend.curve = GradientCurve::CatmullRom;
+    let parsed = parse_fgr(&write_fgr(&gradient)).unwrap();
+    assert_eq!(write_fgr(&parsed), write_fgr(&gradient));
+  }
+
+  #[test]
+  fn fgr_rejects_non_finite_numbers() {
+    let text = "Fractalisator Gradient\nstart: 0 0 0 255\npeak: NaN 1 2 3 255\nend: 0 0 0 255\n";
+    assert_eq!(error_line(parse_fgr(text)), 3);
+    let text = "Fractalisator Gradient\nrepeat: inf\nstart: 0 0 0 255\nend: 0 0 0 255\n";
+    assert_eq!(error_line(parse_fgr(text)), 2);
+  }
+
+  #[test]
+  fn fgr_rejects_malformed_lines() {
+    let text = "Fractalisator Gradient\nstart: 0 0 0\nend: 0 0 0 255\n";
+    assert_eq!(error_line(parse_fgr(text)), 2);
+    let text = "Fractalisator Gradient\npeak: 1.5 1 2 3 255\n";
+    assert_eq!(error_line(parse_fgr(text)), 2);
+    let text = "Fractalisator Gradient\nstart: 0 0 0 255\n";
+    assert_eq!(error_line(parse_fgr(text)), 1);
+  }
+
+  #[test]
+  fn gpl_and_map_reject_bad_colors() {
+    assert_eq!(error_line(parse_gpl("GIMP Palette\n0 0 0\n300 0 0\n")), 3);
+    assert_eq!(error_line(parse_map("0 0 0\n1 2\n")), 2);
+  }
+}
@@ -0,0 +1,209 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rayon::prelude::*;
+
+use fractal::definitions::*;
+
+//==============================================================================
+// Post-processing
+//==============================================================================
+//
+// The finished BGRA image is adjusted with channels on the scale [0, 1].
+// Dithering only makes sense where a high precision image is rounded to 8
+// bits, so it is left to `quantize`. Alpha is kept as it is.
+
+/// Weights of red, green and blue in the luma of a color (Rec. 709).
+const LUMA : [f64; 3] = [0.2126, 0.7152, 0.0722];
+
+/// 8 x 8 Bayer matrix for ordered dithering.
+const BAYER : [[u8; 8]; 8] = [
+  [ 0, 32,  8, 40,  2, 34, 10, 42],
+  [48, 16, 56, 24, 50, 18, 58, 26],
+  [12, 44,  4, 36, 14, 46,  6, 38],
+  [60, 28, 52, 20, 62, 30, 54, 22],
+  [ 3, 35, 11, 43,  1, 33,  9, 41],
+  [51, 19, 59, 27, 49, 17, 57, 25],
+  [15, 47,  7, 39, 13, 45,  5, 37],
+  [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Applies the levels, brightness, contrast, gamma, hue and saturation of
+/// `post` to the RGB color `rgb`.
+fn adjust(post : &PostProcess, rgb : [f64; 3]) -> [f64; 3] {
+  let range = (post.white_level - post.black_level).max(1e-6);
+  let mut rgb = rgb;
+  for c in rgb.iter_mut() {
+    let x = ((*c - post.black_level) / range).max(0.).min(1.);
+    let x = (x - 0.5) * post.contrast + 0.5 + post.brightness;
+    *c = x.max(0.).min(1.).powf(1. / post.gamma.max(1e-3));
+  }
+
+  // Rotate around the gray axis, keeping the luma, then scale the distance
+  // from gray
+  let (sin, cos) = post.hue_shift.to_radians().sin_cos();
+  let [r, g, b] = rgb;
+  let rotated = [
+    r * (0.213 + 0.787 * cos - 0.213 * sin)
+      + g * (0.715 - 0.715 * cos - 0.715 * sin)
+      + b * (0.072 - 0.072 * cos + 0.928 * sin),
+    r * (0.213 - 0.213 * cos + 0.143 * sin)
+      + g * (0.715 + 0.285 * cos + 0.140 * sin)
+      + b * (0.072 - 0.072 * cos - 0.283 * sin),
+    r * (0.213 - 0.213 * cos - 0.787 * sin)
+      + g * (0.715 - 0.715 * cos + 0.715 * sin)
+      + b * (0.072 + 0.928 * cos + 0.072 * sin),
+  ];
+  let luma = LUMA[0] * rotated[0] + LUMA[1] * rotated[1] + LUMA[2] * rotated[2];
+  let mut out = [0.; 3];
+  for c in 0..3 {
+    out[c] = (luma + (rotated[c] - luma) * post.saturation).max(0.).min(1.);
+  }
+  out
+}
+
+/// Returns whether `post` leaves every image unchanged. Dithering does not
+/// count, as it only applies when rounding to 8 bits.
+pub fn is_identity(post : &PostProcess) -> bool {
+  *post == PostProcess{dither : post.dither, ..PostProcess::default()}
+}
+
+/// Applies `post` to the square BGRA image `image` that is `width` pixels
+/// wide.
+pub fn process(image : &mut HdrImage, width : usize, post : &PostProcess) {
+  if is_identity(post) || width == 0 {
+    return
+  }
+
+  // Red, green and blue of every pixel
+  let rgb = image.par_chunks(4)
+    .map(|px| [px[2] as f64, px[1] as f64, px[0] as f64])
+    .collect();
+  let rgb = process_rgb(rgb, width, post);
+
+  image.par_chunks_mut(4).zip(rgb.par_iter()).for_each(|(px, color)| {
+    px[0] = color[2] as f32;
+    px[1] = color[1] as f32;
+    px[2] = color[0] as f32;
+  });
+}
+
+/// Applies every adjustment of `post` to the colors `rgb` of an image that is
+/// `width` pixels wide.
+fn process_rgb(rgb : Vec<[f64; 3]>, width : usize, post : &PostProcess)
+-> Vec<[f64; 3]> {
+  let height = rgb.len() / width;
+  let mut rgb : Vec<[f64; 3]> = rgb.into_par_iter()
+    .map(|color| adjust(post, color))
+    .collect();
+
+  if post.sharpen > 0. {
+    rgb = sharpen(&rgb, width, height, post.sharpen);
+  }
+
+  if post.vignette > 0. {
+    let (cx, cy) = ((width as f64 - 1.) / 2., (height as f64 - 1.) / 2.);
+    let corner = (cx * cx + cy * cy).sqrt().max(1.);
+    rgb.par_iter_mut().enumerate().for_each(|(k, color)| {
+      let (x, y) = ((k % width) as f64 - cx, (k / width) as f64 - cy);
+      let r = (x * x + y * y).sqrt() / corner;
+      // Smooth falloff towards the corners
+      let factor = 1. - post.vignette * r * r * (3. - 2. * r);
+      for c in color.iter_mut() {
+        *c *= factor;
+      }
+    });
+  }
+
+  rgb
+}
+
+/// Sharpens `rgb` with an unsharp mask of strength `amount` over the 3 x 3
+/// neighbourhood of every pixel.
+fn sharpen(rgb : &[[f64; 3]], width : usize, height : usize, amount : f64)
+-> Vec<[f64; 3]> {
+  (0..rgb.len()).into_par_iter()
+    .map(|k| {
+      let (x, y) = (k % width, k / width);
+      let mut blur = [0.; 3];
+      for j in y.saturating_sub(1)..(y + 2).min(height) {
+        for i in x.saturating_sub(1)..(x + 2).min(width) {
+          for c in 0..3 {
+            blur[c] += rgb[j * width + i][c];
+          }
+        }
+      }
+      let count = ((y + 2).min(height) - y.saturating_sub(1))
+        * ((x + 2).min(width) - x.saturating_sub(1));
+      let mut out = rgb[k];
+      for c in 0..3 {
+        let blur = blur[c] / count as f64;
+        out[c] = (out[c] + amount * (out[c] - blur)).max(0.).min(1.);
+      }
+      out
+    })
+    .collect()
+}
+
+//==============================================================================
+// Quantization
+//==============================================================================
+
+/// Rounds the high precision BGRA image `image` that is `width` pixels wide
+/// to 8 bits per channel, dithering the colors as specified.
+pub fn quantize(image : &HdrImage, width : usize, dither : Dither) -> FracImage {
+  let to_u8 = |x : f64| (255. * x).round().max(0.).min(255.) as u8;
+  let mut quantized = vec![0; image.len()];
+
+  match dither {
+    Dither::Off | Dither::Ordered => {
+      quantized.par_chunks_mut(4).zip(image.par_chunks(4)).enumerate()
+        .for_each(|(k, (px, color))| {
+          let offset = match dither {
+            Dither::Ordered => {
+              let threshold = BAYER[(k / width) % 8][k % width % 8] as f64;
+              ((threshold + 0.5) / 64. - 0.5) / 255.
+            }
+            _ => 0.,
+          };
+          for c in 0..3 {
+            px[c] = to_u8(color[c] as f64 + offset);
+          }
+          px[3] = to_u8(color[3] as f64);
+        });
+    }
+    Dither::ErrorDiffusion => {
+      // Every pixel depends on the ones before it, so this runs in order
+      let mut colors : Vec<f64> = image.iter().map(|&c| c as f64).collect();
+      let height = image.len() / 4 / width.max(1);
+      for k in 0..width * height {
+        let (x, y) = (k % width, k / width);
+        quantized[4 * k + 3] = to_u8(colors[4 * k + 3]);
+        for c in 0..3 {
+          let color = colors[4 * k + c];
+          let rounded = to_u8(color);
+          quantized[4 * k + c] = rounded;
+
+          let error = color - rounded as f64 / 255.;
+          let mut spread = |i : usize, j : usize, weight : f64| {
+            colors[4 * (j * width + i) + c] += error * weight;
+          };
+          if x + 1 < width {
+            spread(x + 1, y, 7. / 16.);
+          }
+          if y + 1 < height {
+            if x > 0 {
+              spread(x - 1, y + 1, 3. / 16.);
+            }
+            spread(x, y + 1, 5. / 16.);
+            if x + 1 < width {
+              spread(x + 1, y + 1, 1. / 16.);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  quantized
+}
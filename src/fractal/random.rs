@@ -0,0 +1,113 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use fractal::definitions::*;
+use fractal::space;
+
+//==============================================================================
+// Random gradients
+//==============================================================================
+//
+// Colors are picked in OkLCh so that lightness and chroma stay comparable
+// across hues. The scheme picks the hues around a random base hue, the colors
+// cycle through them while the lightness rises from dark to light. ChaCha8 is
+// portable and stable across releases, so a seed always gives the same
+// gradient.
+
+/// Returns the hues (in turns) of `scheme` around `base`.
+fn scheme_hues<R : Rng>(scheme : ColorScheme, base : f64, rng : &mut R) -> Vec<f64> {
+  match scheme {
+    ColorScheme::Analogous => {
+      let spread = rng.gen_range(0.04, 0.1);
+      vec!(base - spread, base, base + spread)
+    }
+    ColorScheme::Complementary => vec!(base, base + 0.5),
+    ColorScheme::Triadic => vec!(base, base + 1. / 3., base + 2. / 3.),
+    ColorScheme::Monochrome => vec!(base),
+  }
+}
+
+/// Generates the gradient described by `seed`.
+pub fn random_gradient(seed : GradientSeed) -> Gradient {
+  let mut rng = ChaCha8Rng::seed_from_u64(seed.seed as u64);
+  let hues = scheme_hues(seed.scheme, rng.gen::<f64>(), &mut rng);
+  // Number of colors including the start and end color
+  let count = rng.gen_range(4, 8);
+  let (dark, light) = (rng.gen_range(0.05, 0.3), rng.gen_range(0.75, 0.97));
+  let max_chroma = match seed.scheme {
+    ColorScheme::Monochrome => rng.gen_range(0.04, 0.12),
+    _ => rng.gen_range(0.08, 0.18),
+  };
+
+  let colors : Vec<(u8, u8, u8, u8)> = (0..count)
+    .map(|i| {
+      let t = i as f64 / (count - 1) as f64;
+      let lightness = dark + (light - dark) * t + rng.gen_range(-0.04, 0.04);
+      let chroma = max_chroma * rng.gen_range(0.5, 1.);
+      let hue = hues[i % hues.len()] + rng.gen_range(-0.02, 0.02);
+      space::from_space(ColorSpace::Oklch, [hue.rem_euclid(1.), chroma, lightness, 255.])
+    })
+    .collect();
+
+  // Evenly spread peaks moved by at most a third of their spacing
+  let spacing = 1. / (count - 1) as f64;
+  let peaks = (1..count - 1)
+    .map(|i| ColorPeak{
+      bgra : colors[i],
+      at : i as f64 * spacing + rng.gen_range(-spacing / 3., spacing / 3.),
+    })
+    .collect();
+
+  Gradient {
+    start_color : colors[0],
+    peaks : peaks,
+    end_color : colors[count - 1],
+    smooth : true,
+    mapping : GradientMapping::default(),
+    blend : GradientBlend{space : ColorSpace::Oklab, ..GradientBlend::default()},
+  }
+}
+
+/// Picks a new random seed for `scheme`.
+pub fn new_seed(scheme : ColorScheme) -> GradientSeed {
+  GradientSeed{scheme : scheme, seed : rand::thread_rng().gen()}
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static SCHEMES : &[ColorScheme] = &[
+    ColorScheme::Analogous,
+    ColorScheme::Complementary,
+    ColorScheme::Triadic,
+    ColorScheme::Monochrome,
+  ];
+
+  #[test]
+  fn seeds_give_the_same_gradient() {
+    for &scheme in SCHEMES {
+      for seed in 0..20 {
+        let seed = GradientSeed{scheme : scheme, seed : seed};
+        let (a, b) = (random_gradient(seed), random_gradient(seed));
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+      }
+    }
+  }
+
+  #[test]
+  fn seeds_give_the_gradient_of_earlier_releases() {
+    // Seeds saved with exported images have to keep working
+    let gradient = random_gradient(GradientSeed{scheme : ColorScheme::Triadic, seed : 42});
+    assert_eq!(gradient.start_color, (38, 0, 0, 255));
+    assert_eq!(gradient.end_color, (173, 158, 245, 255));
+    assert_eq!(gradient.peaks.len(), 3);
+  }
+}
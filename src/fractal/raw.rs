@@ -0,0 +1,342 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use error::{Error, Result};
+use fractal::definitions::*;
+use fractal::draw;
+
+//==============================================================================
+// Raw fractal data
+//==============================================================================
+//
+// Every point of a fractal is exported as the channels listed in `CHANNELS`,
+// which are the fields of `FracPoint` in the same order. Whole numbers such as
+// `n` are stored as floats too.
+//
+// Binary files (`.frac`) are laid out as follows, all numbers little endian:
+// - the 8 bytes `FRACRAW1`
+// - the length of the header in bytes as a `u32`
+// - the header, UTF-8 `key: value` lines with the `FracArgs` the fractal was
+//   computed with and the `channels` of every point separated by spaces
+// - the points row by row, starting at the smallest real and imaginary part,
+//   every point as one `f64` per channel
+//
+// NumPy files (`.npy`) hold an array of `f64` with the shape `(pixel size,
+// pixel size, channels)`. CSV files start with the header lines prefixed by
+// `# `, followed by a line with the column names and a line per point.
+// Only binary files can be loaded back, as the other formats are meant for
+// analysis elsewhere.
+
+/// Names of the channels of every point.
+pub const CHANNELS : &[&str] = &[
+  "n", "re", "im", "dist", "trap_dist", "trap_step", "stripe", "stripe_prev",
+  "triangle", "triangle_prev", "min_abs", "min_step", "period", "multiplier",
+];
+
+/// Start of every binary raw data file, including the format version.
+const MAGIC : &[u8] = b"FRACRAW1";
+
+/// Returns the channels of `point` in the order of `CHANNELS`.
+fn channels(point : &FracPoint) -> [f64; 14] {
+  [
+    point.n as f64, point.re, point.im, point.dist, point.trap_dist,
+    point.trap_step as f64, point.stripe, point.stripe_prev, point.triangle,
+    point.triangle_prev, point.min_abs, point.min_step as f64,
+    point.period as f64, point.multiplier,
+  ]
+}
+
+/// Creates a point from its channels `c` in the order of `CHANNELS`.
+fn from_channels(c : &[f64]) -> FracPoint {
+  FracPoint {
+    n : c[0] as usize,
+    re : c[1],
+    im : c[2],
+    dist : c[3],
+    trap_dist : c[4],
+    trap_step : c[5] as usize,
+    stripe : c[6],
+    stripe_prev : c[7],
+    triangle : c[8],
+    triangle_prev : c[9],
+    min_abs : c[10],
+    min_step : c[11] as usize,
+    period : c[12] as usize,
+    multiplier : c[13],
+  }
+}
+
+//==============================================================================
+// Header
+//==============================================================================
+
+/// Writes `frac_args` and the channel names as `key: value` lines.
+fn write_header(frac_args : &FracArgs) -> String {
+  let FracArgs{field, trap, ..} = frac_args;
+  let lines = [
+    ("pixel size", field.pixel_size.to_string()),
+    ("center re", field.center_re.to_string()),
+    ("center im", field.center_im.to_string()),
+    ("radius", field.radius.to_string()),
+    ("c re", frac_args.c_re.to_string()),
+    ("c im", frac_args.c_im.to_string()),
+    ("steps", frac_args.steps.to_string()),
+    ("iteration bound", frac_args.iter_bound.to_string()),
+    ("iteration style", frac_args.iteration_style.to_string()),
+    ("iterator", frac_args.iterator_kind.to_string()),
+    ("trap shape", trap.shape.to_string()),
+    ("trap re", trap.re.to_string()),
+    ("trap im", trap.im.to_string()),
+    ("trap radius", trap.radius.to_string()),
+    ("trap angle", trap.angle.to_string()),
+    ("stripe density", frac_args.stripe_density.to_string()),
+    ("find cycles", frac_args.find_cycles.to_string()),
+    ("channels", CHANNELS.join(" ")),
+  ];
+  lines.iter().map(|(key, value)| format!("{}: {}\n", key, value)).collect()
+}
+
+/// Finds the option of `all` written as `value`.
+fn parse_choice<T : Copy + ToString>(all : &[T], value : &str) -> Result<T> {
+  all.iter().cloned().find(|option| option.to_string() == value)
+    .ok_or_else(|| Error::RawData(format!("unknown option '{}'", value)))
+}
+
+/// Parses the number `value` of `key`.
+fn parse_number<T : std::str::FromStr>(key : &str, value : &str) -> Result<T> {
+  value.parse::<T>()
+    .map_err(|_| Error::RawData(format!("'{}' is not a valid {}", value, key)))
+}
+
+/// Parses the finite number `value` of `key`.
+fn parse_float(key : &str, value : &str) -> Result<f64> {
+  match parse_number::<f64>(key, value)? {
+    x if x.is_finite() => Ok(x),
+    _ => Err(Error::RawData(format!("'{}' is not a valid {}", value, key))),
+  }
+}
+
+/// Reads the fractal arguments from the `key: value` lines of `header`.
+/// Missing keys keep their default values.
+fn parse_header(header : &str) -> Result<FracArgs> {
+  let mut args = FracArgs::default();
+  for line in header.lines().filter(|line| !line.trim().is_empty()) {
+    let mut parts = line.splitn(2, ':');
+    let key = parts.next().unwrap_or("").trim();
+    let value = parts.next()
+      .ok_or_else(|| Error::RawData(format!("expected 'key: value', found '{}'", line)))?
+      .trim();
+    match key {
+      "pixel size" => args.field.pixel_size = parse_number(key, value)?,
+      "center re" => args.field.center_re = parse_float(key, value)?,
+      "center im" => args.field.center_im = parse_float(key, value)?,
+      "radius" => args.field.radius = parse_float(key, value)?,
+      "c re" => args.c_re = parse_float(key, value)?,
+      "c im" => args.c_im = parse_float(key, value)?,
+      "steps" => args.steps = parse_number(key, value)?,
+      "iteration bound" => args.iter_bound = parse_float(key, value)?,
+      "iteration style" => args.iteration_style = parse_choice(ALL_ITERATIONS, value)?,
+      "iterator" => args.iterator_kind = parse_choice(ALL_ITERATORS, value)?,
+      "trap shape" => args.trap.shape = parse_choice(ALL_TRAP_SHAPES, value)?,
+      "trap re" => args.trap.re = parse_float(key, value)?,
+      "trap im" => args.trap.im = parse_float(key, value)?,
+      "trap radius" => args.trap.radius = parse_float(key, value)?,
+      "trap angle" => args.trap.angle = parse_float(key, value)?,
+      "stripe density" => args.stripe_density = parse_float(key, value)?,
+      "find cycles" => args.find_cycles = parse_number(key, value)?,
+      "channels" => {
+        if value.split_whitespace().ne(CHANNELS.iter().cloned()) {
+          return Err(Error::RawData(format!("unsupported channels '{}'", value)))
+        }
+      }
+      _ => return Err(Error::RawData(format!("unknown key '{}'", key))),
+    }
+  }
+  if args.field.pixel_size == 0 {
+    return Err(Error::RawData("the pixel size is 0".to_string()))
+  }
+  Ok(args)
+}
+
+//==============================================================================
+// Saving
+//==============================================================================
+
+/// Writes `fractal` computed with `frac_args` to `path` in `format`.
+pub fn save_raw(path : &Path, fractal : &RawFrac, frac_args : &FracArgs, format : DataFormat)
+-> Result<()> {
+  let pixel_size = frac_args.field.pixel_size;
+  if fractal.len() != pixel_size * pixel_size {
+    return Err(Error::BufferSize{expected : pixel_size * pixel_size, found : fractal.len()})
+  }
+  let mut file = BufWriter::new(File::create(path)?);
+  let header = write_header(frac_args);
+
+  match format {
+    DataFormat::Binary => {
+      file.write_all(MAGIC)?;
+      file.write_all(&(header.len() as u32).to_le_bytes())?;
+      file.write_all(header.as_bytes())?;
+      write_points(&mut file, fractal)?;
+    }
+    DataFormat::Npy => {
+      let dict = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+        pixel_size, pixel_size, CHANNELS.len());
+      // The header is padded with spaces so that the data is aligned to 64
+      // bytes, magic string and lengths take 10 bytes
+      let padding = 63 - (10 + dict.len()) % 64;
+      let dict = format!("{}{}\n", dict, " ".repeat(padding));
+      file.write_all(b"\x93NUMPY\x01\x00")?;
+      file.write_all(&(dict.len() as u16).to_le_bytes())?;
+      file.write_all(dict.as_bytes())?;
+      write_points(&mut file, fractal)?;
+    }
+    DataFormat::Csv => {
+      for line in header.lines() {
+        writeln!(file, "# {}", line)?;
+      }
+      writeln!(file, "x,y,{}", CHANNELS.join(","))?;
+      for (k, point) in fractal.iter().enumerate() {
+        write!(file, "{},{}", k % pixel_size, k / pixel_size)?;
+        for c in channels(point).iter() {
+          write!(file, ",{}", c)?;
+        }
+        writeln!(file)?;
+      }
+    }
+  }
+  file.flush()?;
+  Ok(())
+}
+
+/// Writes the channels of every point of `fractal` as little endian `f64`.
+fn write_points<W : Write>(file : &mut W, fractal : &RawFrac) -> Result<()> {
+  for point in fractal.iter() {
+    for c in channels(point).iter() {
+      file.write_all(&c.to_le_bytes())?;
+    }
+  }
+  Ok(())
+}
+
+//==============================================================================
+// Loading
+//==============================================================================
+
+/// Loads a binary raw data file from `path`. Returns the fractal and the
+/// arguments it was computed with, so it can be recolored without computing
+/// it again. Fails before the points are read if the fractal would not fit
+/// into memory when colored with `img_args`.
+pub fn load_raw(path : &str, img_args : &ImageArgs) -> Result<(FracArgs, RawFrac)> {
+  let bytes = fs::read(path)?;
+  if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+    return Err(Error::RawData("not a raw fractal data file".to_string()))
+  }
+  let mut length = [0; 4];
+  length.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+  let header_start = MAGIC.len() + 4;
+  let data_start = header_start + u32::from_le_bytes(length) as usize;
+  if bytes.len() < data_start {
+    return Err(Error::RawData("the header is cut off".to_string()))
+  }
+  let header = std::str::from_utf8(&bytes[header_start..data_start])
+    .map_err(|_| Error::RawData("the header is not UTF-8".to_string()))?;
+  let args = parse_header(header)?;
+
+  let pixel_size = args.field.pixel_size;
+  draw::check_memory(pixel_size, img_args)?;
+  let record = 8 * CHANNELS.len();
+  let data = &bytes[data_start..];
+  if data.len() != pixel_size * pixel_size * record {
+    return Err(Error::BufferSize{expected : pixel_size * pixel_size * record, found : data.len()})
+  }
+
+  let fractal = data.chunks(record)
+    .map(|point| {
+      let values : Vec<f64> = point.chunks(8)
+        .map(|c| {
+          let mut value = [0; 8];
+          value.copy_from_slice(c);
+          f64::from_le_bytes(value)
+        })
+        .collect();
+      from_channels(&values)
+    })
+    .collect();
+
+  Ok((args, fractal))
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Returns a path in the temporary directory that no other test uses.
+  fn temp_path(name : &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("fractalisator_{}_{}", std::process::id(), name))
+  }
+
+  /// Returns a small fractal with arguments that all differ from the defaults.
+  fn fractal() -> (FracArgs, RawFrac) {
+    let mut args = FracArgs::default();
+    args.field.pixel_size = 7;
+    args.field.center_re = -0.25;
+    args.field.radius = 1.5;
+    args.c_re = -0.8;
+    args.c_im = 0.156;
+    args.steps = 100;
+    args.iteration_style = IterationStyle::Mandelbrot;
+    args.iterator_kind = IteratorKind::Cube;
+    args.trap.shape = TrapShape::Circle;
+    args.trap.angle = 0.5;
+    args.find_cycles = true;
+    (args, draw::compute_fractal(args))
+  }
+
+  #[test]
+  fn binary_files_round_trip() {
+    let (args, fractal) = fractal();
+    let path = temp_path("round_trip.frac");
+    save_raw(&path, &fractal, &args, DataFormat::Binary).unwrap();
+    let (loaded_args, loaded) = load_raw(path.to_str().unwrap(), &ImageArgs::default()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(write_header(&loaded_args), write_header(&args));
+    assert_eq!(loaded.len(), fractal.len());
+    for (a, b) in loaded.iter().zip(fractal.iter()) {
+      assert_eq!(channels(a)[..], channels(b)[..]);
+    }
+  }
+
+  #[test]
+  fn npy_data_is_aligned() {
+    let (args, fractal) = fractal();
+    let path = temp_path("aligned.npy");
+    save_raw(&path, &fractal, &args, DataFormat::Npy).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let header_length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + header_length) % 64, 0);
+    assert_eq!(bytes[10 + header_length - 1], b'\n');
+    assert_eq!(bytes.len(), 10 + header_length + 8 * CHANNELS.len() * fractal.len());
+  }
+
+  #[test]
+  fn headers_reject_invalid_numbers() {
+    assert!(parse_header("pixel size: 0\n").is_err());
+    assert!(parse_header("pixel size: 10\nradius: NaN\n").is_err());
+    assert!(parse_header("pixel size: 10\nc re: inf\n").is_err());
+    assert!(parse_header("pixel size: 10\nsteps: -1\n").is_err());
+    assert!(parse_header("pixel size: 10\nradius: 2.5\n").is_ok());
+  }
+}
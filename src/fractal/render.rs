@@ -0,0 +1,59 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use error::{Error, Result};
+use fractal::alpha;
+use fractal::color;
+use fractal::definitions::*;
+use fractal::draw;
+use fractal::lighting;
+use fractal::post;
+use fractal::texture;
+use fractal::texture::TextureHits;
+
+//==============================================================================
+// Rendering
+//==============================================================================
+//
+// The preview and exports go through the same stages, so an export looks like
+// the preview at a different resolution. Every stage works in high precision,
+// images are only rounded to 8 bits at the very end.
+
+/// Colors `fractal` computed with `frac_args` through every stage of
+/// `img_args`: the coloring with its layers and texture, lighting,
+/// post-processing and transparency. Texture hits are taken from `hits` if
+/// they fit and stored there otherwise. Fails if `fractal` does not have the
+/// size `frac_args` describes or the stages of `img_args` would not fit into
+/// memory.
+pub fn color_image_hdr(
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs,
+  hits : &mut Option<TextureHits>)
+-> Result<HdrImage> {
+  let pixel_size = frac_args.field.pixel_size;
+  if fractal.len() != pixel_size * pixel_size {
+    return Err(Error::BufferSize{expected : pixel_size * pixel_size, found : fractal.len()})
+  }
+  draw::check_memory(pixel_size, img_args)?;
+  let mut image = color::color_fractal(fractal, frac_args, img_args);
+  if let Some(hits) = texture::cached_hits(hits, frac_args, img_args) {
+    texture::blend_hits(&mut image, &hits.hits);
+  }
+  lighting::light_image(&mut image, fractal, frac_args, &img_args.lighting);
+  post::process(&mut image, pixel_size, &img_args.post);
+  alpha::make_transparent(&mut image, fractal, frac_args, img_args);
+  Ok(image)
+}
+
+/// Like `color_image_hdr`, but the image is rounded to 8 bits with the
+/// dithering of `img_args`.
+pub fn color_image(
+  fractal : &RawFrac,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs,
+  hits : &mut Option<TextureHits>)
+-> Result<FracImage> {
+  let image = color_image_hdr(fractal, frac_args, img_args, hits)?;
+  Ok(post::quantize(&image, frac_args.field.pixel_size, img_args.post.dither))
+}
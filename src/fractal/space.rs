@@ -0,0 +1,276 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use fractal::definitions::*;
+
+//==============================================================================
+// Color space conversions
+//==============================================================================
+//
+// Colors are converted from BGRA `u8` tuples to `[f64; 4]` arrays in the
+// chosen color space. Alpha is always kept in the last channel on the scale
+// [0, 255]. The other channels are:
+// - `Srgb` blue, green and red on the scale [0, 255]
+// - `LinearRgb` linear blue, green and red on the scale [0, 1]
+// - `Oklab` lightness, a and b
+// - `Oklch` hue (in turns), chroma and lightness
+// - `Hsv` hue (in turns), saturation and value
+// - `Hsl` hue (in turns), saturation and lightness
+//
+// Hue based spaces keep the hue in the first and the chroma or saturation in
+// the second channel.
+
+/// Converts an sRGB channel from [0, 1] to linear light.
+pub fn srgb_to_linear(c : f64) -> f64 {
+  if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts a linear light channel from [0, 1] to sRGB.
+fn linear_to_srgb(c : f64) -> f64 {
+  if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+}
+
+/// Converts linear RGB to Oklab `(L, a, b)`.
+fn linear_to_oklab(r : f64, g : f64, b : f64) -> (f64, f64, f64) {
+  let l = 0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b;
+  let m = 0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b;
+  let s = 0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b;
+  let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+  (
+    0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+    1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+    0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+  )
+}
+
+/// Converts Oklab `(L, a, b)` to linear RGB.
+fn oklab_to_linear(l : f64, a : f64, b : f64) -> (f64, f64, f64) {
+  let l_ = l + 0.3963377774 * a + 0.2158037573 * b;
+  let m_ = l - 0.1055613458 * a - 0.0638541728 * b;
+  let s_ = l - 0.0894841775 * a - 1.2914855480 * b;
+  let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+  (
+    4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+    -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+    -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+  )
+}
+
+/// Returns the hue (in turns) of the sRGB color `(r, g, b)` from [0, 1]
+/// together with its largest and smallest channel.
+fn hue(r : f64, g : f64, b : f64) -> (f64, f64, f64) {
+  let max = r.max(g).max(b);
+  let min = r.min(g).min(b);
+  let delta = max - min;
+  let h =
+    if delta == 0. {
+      0.
+    } else if max == r {
+      ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+      (b - r) / delta + 2.
+    } else {
+      (r - g) / delta + 4.
+    };
+  (h / 6., max, min)
+}
+
+/// Converts the hue (in turns), chroma and the smallest channel of a color
+/// back to sRGB `(r, g, b)` from [0, 1].
+fn from_hue(h : f64, chroma : f64, min : f64) -> (f64, f64, f64) {
+  let h = h.rem_euclid(1.) * 6.;
+  let x = chroma * (1. - (h.rem_euclid(2.) - 1.).abs());
+  let (r, g, b) = match h as usize {
+    0 => (chroma, x, 0.),
+    1 => (x, chroma, 0.),
+    2 => (0., chroma, x),
+    3 => (0., x, chroma),
+    4 => (x, 0., chroma),
+    _ => (chroma, 0., x),
+  };
+  (r + min, g + min, b + min)
+}
+
+/// Converts the BGRA color `bgra` to channels of `space`.
+pub fn to_space(space : ColorSpace, bgra : (u8, u8, u8, u8)) -> [f64; 4] {
+  let (b, g, r, a) = bgra;
+  let (b, g, r, a) = (b as f64, g as f64, r as f64, a as f64);
+  if space == ColorSpace::Srgb {
+    return [b, g, r, a]
+  }
+
+  let (r, g, b) = (r / 255., g / 255., b / 255.);
+  let linear = || (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+
+  match space {
+    ColorSpace::Srgb => [b, g, r, a],
+    ColorSpace::LinearRgb => {
+      let (r, g, b) = linear();
+      [b, g, r, a]
+    }
+    ColorSpace::Oklab => {
+      let (r, g, b) = linear();
+      let (l, ok_a, ok_b) = linear_to_oklab(r, g, b);
+      [l, ok_a, ok_b, a]
+    }
+    ColorSpace::Oklch => {
+      let (r, g, b) = linear();
+      let (l, ok_a, ok_b) = linear_to_oklab(r, g, b);
+      let h = ok_b.atan2(ok_a) / (2. * std::f64::consts::PI);
+      [h.rem_euclid(1.), (ok_a * ok_a + ok_b * ok_b).sqrt(), l, a]
+    }
+    ColorSpace::Hsv => {
+      let (h, max, min) = hue(r, g, b);
+      let s = if max > 0. { (max - min) / max } else { 0. };
+      [h, s, max, a]
+    }
+    ColorSpace::Hsl => {
+      let (h, max, min) = hue(r, g, b);
+      let l = (max + min) / 2.;
+      let s =
+        if max == min { 0. } else { (max - min) / (1. - (2. * l - 1.).abs()) };
+      [h, s, l, a]
+    }
+  }
+}
+
+/// Converts channels of `space` back to a BGRA color.
+pub fn from_space(space : ColorSpace, c : [f64; 4]) -> (u8, u8, u8, u8) {
+  let to_u8 = |x : f64| (x.round() as isize).min(255).max(0) as u8;
+  if space == ColorSpace::Srgb {
+    return (to_u8(c[0]), to_u8(c[1]), to_u8(c[2]), to_u8(c[3]))
+  }
+
+  let (r, g, b) = to_unit_rgb(space, c);
+  let to_u8_unit = |x : f64| to_u8(255. * x);
+  (to_u8_unit(b), to_u8_unit(g), to_u8_unit(r), to_u8(c[3]))
+}
+
+/// Converts channels of `space` back to a BGRA color with channels on the
+/// scale [0, 1], without rounding them to 8 bits.
+pub fn from_space_unit(space : ColorSpace, c : [f64; 4]) -> [f64; 4] {
+  let clip = |x : f64| x.max(0.).min(1.);
+  if space == ColorSpace::Srgb {
+    return [clip(c[0] / 255.), clip(c[1] / 255.), clip(c[2] / 255.), clip(c[3] / 255.)]
+  }
+
+  let (r, g, b) = to_unit_rgb(space, c);
+  [clip(b), clip(g), clip(r), clip(c[3] / 255.)]
+}
+
+/// Converts channels of any `space` but `Srgb` to sRGB red, green and blue on
+/// the scale [0, 1].
+fn to_unit_rgb(space : ColorSpace, c : [f64; 4]) -> (f64, f64, f64) {
+  match space {
+    ColorSpace::Srgb => unreachable!(),
+    ColorSpace::LinearRgb =>
+      (linear_to_srgb(c[2]), linear_to_srgb(c[1]), linear_to_srgb(c[0])),
+    ColorSpace::Oklab | ColorSpace::Oklch => {
+      let (l, ok_a, ok_b) =
+        if space == ColorSpace::Oklab {
+          (c[0], c[1], c[2])
+        } else {
+          let angle = 2. * std::f64::consts::PI * c[0];
+          (c[2], c[1] * angle.cos(), c[1] * angle.sin())
+        };
+      let (r, g, b) = oklab_to_linear(l, ok_a, ok_b);
+      let clip = |x : f64| x.max(0.).min(1.);
+      (linear_to_srgb(clip(r)), linear_to_srgb(clip(g)), linear_to_srgb(clip(b)))
+    }
+    ColorSpace::Hsv => {
+      let chroma = c[2] * c[1];
+      from_hue(c[0], chroma, c[2] - chroma)
+    }
+    ColorSpace::Hsl => {
+      let chroma = (1. - (2. * c[2] - 1.).abs()) * c[1];
+      from_hue(c[0], chroma, c[2] - chroma / 2.)
+    }
+  }
+}
+
+/// Returns whether the first channel of `space` is a hue.
+pub fn has_hue(space : ColorSpace) -> bool {
+  match space {
+    ColorSpace::Oklch | ColorSpace::Hsv | ColorSpace::Hsl => true,
+    _ => false,
+  }
+}
+
+/// Returns the difference from hue `h1` to hue `h2` (both in turns) when
+/// going around the color wheel along `path`.
+pub fn hue_difference(path : HuePath, h1 : f64, h2 : f64) -> f64 {
+  let d = h2 - h1;
+  match path {
+    HuePath::Shorter =>
+      if d > 0.5 { d - 1. } else if d < -0.5 { d + 1. } else { d },
+    HuePath::Longer =>
+      if d > 0. && d < 0.5 { d - 1. } else if d < 0. && d > -0.5 { d + 1. } else { d },
+    HuePath::Increasing => if d < 0. { d + 1. } else { d },
+    HuePath::Decreasing => if d > 0. { d - 1. } else { d },
+  }
+}
+
+/// Linearly blends channels `c1` and `c2` of `space` with the fraction `f` of
+/// `c2`. Hues move along `path` and grays take the hue of the other color.
+pub fn mix(
+  space : ColorSpace,
+  path : HuePath,
+  c1 : [f64; 4],
+  c2 : [f64; 4],
+  f : f64)
+-> [f64; 4] {
+  let mut mixed = [0.; 4];
+  for i in 0..4 {
+    mixed[i] = c1[i] * (1. - f) + c2[i] * f;
+  }
+
+  if has_hue(space) {
+    // Grays have no meaningful hue
+    let (h1, h2) =
+      if c1[1] < 1e-6 {
+        (c2[0], c2[0])
+      } else if c2[1] < 1e-6 {
+        (c1[0], c1[0])
+      } else {
+        (c1[0], c2[0])
+      };
+    mixed[0] = (h1 + f * hue_difference(path, h1, h2)).rem_euclid(1.);
+  }
+
+  mixed
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn colors_survive_every_space() {
+    for &space in ALL_COLOR_SPACES {
+      for b in (0..256).step_by(15) {
+        for g in (0..256).step_by(15) {
+          for r in (0..256).step_by(15) {
+            let bgra = (b as u8, g as u8, r as u8, 200);
+            assert_eq!(from_space(space, to_space(space, bgra)), bgra, "in {}", space);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn unit_colors_match_byte_colors() {
+    for &space in ALL_COLOR_SPACES {
+      let bgra = (30, 140, 250, 128);
+      let unit = from_space_unit(space, to_space(space, bgra));
+      let (b, g, r, a) = bgra;
+      for (&c, &byte) in unit.iter().zip([b, g, r, a].iter()) {
+        assert!((255. * c - byte as f64).abs() < 1e-3, "{:?} in {}", unit, space);
+      }
+    }
+  }
+}
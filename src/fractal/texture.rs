@@ -0,0 +1,208 @@
+//==============================================================================
+// Open crates and libraries
+//==============================================================================
+use image as imagetool;
+
+use fractal::definitions::*;
+use fractal::draw;
+
+//==============================================================================
+// Texture loading
+//==============================================================================
+
+/// Loads the image at `path` as an orbit trap texture.
+pub fn load_texture(path : &str) -> imagetool::ImageResult<TextureTrap> {
+  let image = imagetool::open(path)?.to_bgra8();
+  let (width, height) = image.dimensions();
+
+  Ok(TextureTrap{
+    pixels : image.into_raw(),
+    width : width as usize,
+    height : height as usize,
+  })
+}
+
+//==============================================================================
+// Texture sampling
+//==============================================================================
+
+/// Returns the BGRA color of `texture` placed over the orbit trap `trap` at
+/// the complex point `(re, im)` or `None` if the point lies outside of the
+/// texture.
+pub fn sample_texture(texture : &TextureTrap, trap : &OrbitTrap, re : f64, im : f64) 
+-> Option<(u8, u8, u8, u8)> {
+  let TextureTrap{width, height, ..} = *texture;
+  let size = 2. * trap.radius;
+  let tex_height = size * (height as f64) / (width as f64);
+
+  // Relative position within the texture rectangle
+  let x_rel = (re - trap.re) / size + 0.5;
+  let y_rel = (im - trap.im) / tex_height + 0.5;
+  if x_rel < 0. || x_rel >= 1. || y_rel < 0. || y_rel >= 1. {
+    return None
+  }
+
+  let i = ((x_rel * width as f64) as usize).min(width - 1);
+  let j = ((y_rel * height as f64) as usize).min(height - 1);
+  let k = 4 * (j * width + i);
+  let px = &texture.pixels[k..k + 4];
+  Some((px[0], px[1], px[2], px[3]))
+}
+
+/// Adds the texture color at the orbit point `(re, im)` to the accumulated
+/// premultiplied BGRA color `acc` according to `hit`.
+fn add_hit(
+  texture : &TextureTrap,
+  trap : &OrbitTrap,
+  hit : TextureHit,
+  acc : (f64, f64, f64, f64),
+  re : f64, im : f64)
+-> (f64, f64, f64, f64) {
+  let (b, g, r, a) = match sample_texture(texture, trap, re, im) {
+    Some((_, _, _, 0)) | None => return acc,
+    Some(bgra) => bgra,
+  };
+  // Premultiply the sampled color
+  let alpha = a as f64 / 255.;
+  let sample = (b as f64 * alpha, g as f64 * alpha, r as f64 * alpha, alpha);
+
+  match hit {
+    TextureHit::First => if acc.3 > 0. { acc } else { sample },
+    TextureHit::Last => sample,
+    TextureHit::Composite => {
+      // Later hits lie beneath the earlier ones
+      let rest = 1. - acc.3;
+      (acc.0 + rest * sample.0, acc.1 + rest * sample.1,
+       acc.2 + rest * sample.2, acc.3 + rest * sample.3)
+    }
+  }
+}
+
+//==============================================================================
+// Texture Hits
+//==============================================================================
+
+/// Returns the premultiplied BGRA texture color collected along the orbit of
+/// every point, combined as specified by `img_args.texture_hit`. The texture
+/// is placed over the orbit trap of `frac_args`.
+pub fn texture_hits(
+  frac_args : &FracArgs,
+  img_args : &ImageArgs,
+  texture : &TextureTrap)
+-> Vec<(f64, f64, f64, f64)> {
+  let hit = img_args.texture_hit;
+  let trap = frac_args.trap;
+  draw::fold_orbits(*frac_args, (0., 0., 0., 0.), |acc, re, im|{
+    add_hit(texture, &trap, hit, acc, re, im)
+  })
+}
+
+/// Texture hits of every point of a fractal, kept together with what they
+/// were traced for. Tracing the orbits takes as long as computing the fractal
+/// again, so the hits are reused until the fractal arguments, the texture or
+/// the way hits are combined change.
+pub struct TextureHits {
+  frac_args : FracArgs,
+  texture : TextureTrap,
+  hit : TextureHit,
+  pub hits : Vec<(f64, f64, f64, f64)>,
+}
+
+impl TextureHits {
+
+  /// Traces the hits of `texture` for the fractal `frac_args` describes.
+  pub fn new(frac_args : &FracArgs, img_args : &ImageArgs, texture : &TextureTrap)
+  -> TextureHits {
+    TextureHits{
+      frac_args : *frac_args,
+      texture : texture.clone(),
+      hit : img_args.texture_hit,
+      hits : texture_hits(frac_args, img_args, texture),
+    }
+  }
+
+  /// Whether the hits were traced for `frac_args` and the texture of
+  /// `img_args`.
+  pub fn fits(&self, frac_args : &FracArgs, img_args : &ImageArgs) -> bool {
+    self.frac_args == *frac_args
+      && self.hit == img_args.texture_hit
+      && img_args.texture.as_ref() == Some(&self.texture)
+  }
+}
+
+/// Returns the texture hits of `img_args` for the fractal `frac_args`, or
+/// `None` if there is no texture. The hits in `cache` are reused if they fit
+/// and replaced otherwise.
+pub fn cached_hits<'a>(
+  cache : &'a mut Option<TextureHits>,
+  frac_args : &FracArgs,
+  img_args : &ImageArgs)
+-> Option<&'a TextureHits> {
+  let texture = match img_args.texture {
+    Some(ref texture) => texture,
+    None => {
+      *cache = None;
+      return None
+    }
+  };
+  let fits = cache.as_ref().map_or(false, |cached| cached.fits(frac_args, img_args));
+  if !fits {
+    *cache = Some(TextureHits::new(frac_args, img_args, texture));
+  }
+  cache.as_ref()
+}
+
+/// Blends the premultiplied texture `hits` over the `colored` image.
+pub fn blend_hits(colored : &mut HdrImage, hits : &[(f64, f64, f64, f64)]) {
+  for (pixel, &(b, g, r, a)) in colored.chunks_mut(4).zip(hits.iter()) {
+    let rest = 1. - a;
+    pixel[0] = (b / 255. + rest * pixel[0] as f64) as f32;
+    pixel[1] = (g / 255. + rest * pixel[1] as f64) as f32;
+    pixel[2] = (r / 255. + rest * pixel[2] as f64) as f32;
+    pixel[3] = (a + rest * pixel[3] as f64) as f32;
+  }
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A small fractal textured with a single opaque red pixel.
+  fn textured() -> (FracArgs, ImageArgs) {
+    let mut frac_args = FracArgs::default();
+    frac_args.field.pixel_size = 16;
+    let mut img_args = ImageArgs::default();
+    img_args.texture = Some(TextureTrap{pixels : vec![0, 0, 255, 255], width : 1, height : 1});
+    (frac_args, img_args)
+  }
+
+  #[test]
+  fn cached_hits_are_reused_until_the_trap_moves() {
+    let (mut frac_args, img_args) = textured();
+    let mut cache = None;
+    cached_hits(&mut cache, &frac_args, &img_args);
+    // Marks the traced hits so that a reuse can be told from a new trace
+    cache.as_mut().unwrap().hits[0] = (1., 2., 3., 4.);
+    let hits = cached_hits(&mut cache, &frac_args, &img_args).unwrap();
+    assert_eq!(hits.hits[0], (1., 2., 3., 4.));
+
+    frac_args.trap.re += 0.5;
+    let hits = cached_hits(&mut cache, &frac_args, &img_args).unwrap();
+    assert_ne!(hits.hits[0], (1., 2., 3., 4.));
+    assert_eq!(hits.hits.len(), 16 * 16);
+  }
+
+  #[test]
+  fn cached_hits_are_dropped_with_the_texture() {
+    let (frac_args, mut img_args) = textured();
+    let mut cache = None;
+    assert!(cached_hits(&mut cache, &frac_args, &img_args).is_some());
+    img_args.texture = None;
+    assert!(cached_hits(&mut cache, &frac_args, &img_args).is_none());
+    assert!(cache.is_none());
+  }
+}
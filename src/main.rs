@@ -5,12 +5,16 @@ extern crate iced;
 extern crate image;
 extern crate rayon;
 extern crate num;
+extern crate png;
+extern crate rand;
+extern crate rand_chacha;
 extern crate resize;
 
+mod error;
 mod ui;
 mod fractal;
 
-use iced::Sandbox;
+use iced::Application;
 use iced::Settings;
 use ui::app::MainWindow as app;
 
@@ -1,21 +1,44 @@
 //==============================================================================
 // Open crates and libraries
 //==============================================================================
-use image as imagetool;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use iced::{button, Button};
+use iced::{button, Button, Checkbox};
+use iced::canvas::{self, Canvas, Cursor, Frame, Geometry, Path};
 use iced::{image, Image, Text};
 use iced::{pick_list, PickList};
 use iced::{slider, Slider};
-use iced::{Align, Column, Container, Element, Row, Sandbox};
+use iced::{scrollable, Scrollable};
+use iced::{text_input, TextInput};
+use iced::{executor, time, Application, Command, Subscription};
+use iced::futures::future;
+use iced::{Align, Column, Container, Element, Row};
 use iced::{Background, Color, HorizontalAlignment, Length, Vector};
+use iced::{mouse, Point, Rectangle};
 
+use fractal::algorithms;
+use fractal::algorithms::Algorithm;
 use fractal::color;
+use fractal::cycle::PaletteCycle;
 use fractal::definitions;
 use fractal::definitions::FracArgs;
-use fractal::definitions::{Gradient, GradientPreset, IterationStyle, IteratorKind};
+use fractal::definitions::{ColorPeak, Gradient, IterationStyle, IteratorKind};
+use fractal::definitions::{ColorScheme, ColorSpace, GradientCurve, GradientSeed, GradientMapping, GradientWrap, HuePath, IndexTransform};
+use fractal::definitions::{DataFormat, ExportArgs, ExportFormat, FileNaming};
+use fractal::definitions::{BlendMode, Dither, HeightSource, Layer, LayerMask, LightingModel, PaletteOrder, PostProcess, TextureHit, TrapShape};
 use fractal::draw;
+use fractal::export;
+use fractal::extract;
+use fractal::palette;
+use fractal::random;
+use fractal::raw;
+use fractal::render;
+use fractal::space;
+use fractal::texture;
 
+use error::Result;
 use ui::state::*;
 
 //==============================================================================
@@ -25,11 +48,14 @@ use ui::state::*;
 pub enum Layout {
   FracOptions,
   ColorOptions,
+  ExportOptions,
 }
 
 //==============================================================================
 // States of application parts
 //==============================================================================
+/// The application window. The `status` reports the last failed render or
+/// the outcome of the last export.
 pub struct MainWindow {
   frac_state: FractalState,
   image_state: ImageState,
@@ -37,9 +63,13 @@ pub struct MainWindow {
   app_state: AppState,
   frac_layout: FracLayout,
   color_layout: ColorLayout,
+  export_layout: ExportLayout,
+  cycle: Option<PaletteCycle>,
+  status: String,
   save_button: button::State,
   to_frac_layout_button: button::State,
   to_color_layout_button: button::State,
+  to_export_layout_button: button::State,
 }
 
 impl Default for MainWindow {
@@ -48,23 +78,36 @@ impl Default for MainWindow {
     let default_image_state = ImageState::default();
     let pix_size = default_frac_state.args.field.pixel_size;
     let displayed_image = color::resize_fractal_image(&default_image_state.image.clone(), pix_size, 1000);
+    // Presets that could not be loaded are reported in the Color Options
+    let mut color_layout = ColorLayout::default();
+    color_layout.palette_status = default_image_state.presets.errors.join("\n");
     MainWindow {
       frac_state: default_frac_state,
       image_state: default_image_state,
       displayed_image: displayed_image,
       app_state: AppState::default(),
       frac_layout: FracLayout::default(),
-      color_layout: ColorLayout::default(),
+      color_layout: color_layout,
+      export_layout: ExportLayout::default(),
+      cycle: None,
+      status: String::new(),
       save_button: button::State::default(),
       to_frac_layout_button: button::State::default(),
       to_color_layout_button: button::State::default(),
+      to_export_layout_button: button::State::default(),
     }
   }
 }
 
+/// Describes the application. When `cycling` the gradient offset moves by
+/// `cycle_speed` gradient lengths per second, `last_tick` is the time of the
+/// last animation frame.
 pub struct AppState {
   layout: Layout,
   log_increment_size: f64,
+  cycling: bool,
+  cycle_speed: f64,
+  last_tick: Option<Instant>,
 }
 
 impl Default for AppState {
@@ -72,10 +115,16 @@ impl Default for AppState {
     AppState {
       layout: Layout::FracOptions,
       log_increment_size: -6.,
+      cycling: false,
+      cycle_speed: 0.1,
+      last_tick: None,
     }
   }
 }
 
+/// Time between palette cycling frames, about 30 frames per second.
+const CYCLE_FRAME: Duration = Duration::from_millis(33);
+
 #[derive(Default)]
 pub struct FracLayout {
   navigation_buttons: NavigationButtons,
@@ -114,7 +163,137 @@ pub struct ColorLayout {
   steps_up_button: button::State,
   steps_down_button: button::State,
   image_adjustment_buttons: ImageAdjustmentButtons,
-  change_color_button: button::State, // temporary
+  preset_search_input: text_input::State,
+  preset_search: String,
+  change_preset_list: pick_list::State<String>,
+  preset_name_input: text_input::State,
+  preset_name: String,
+  save_preset_button: button::State,
+  randomize_button: button::State,
+  seed_input: text_input::State,
+  seed_text: String,
+  change_scheme_list: pick_list::State<ColorScheme>,
+  palette_path_input: text_input::State,
+  import_palette_button: button::State,
+  extract_palette_button: button::State,
+  extract_colors_slider: slider::State,
+  change_extract_order_list: pick_list::State<PaletteOrder>,
+  palette_path: String,
+  palette_status: String,
+  change_exterior_list: pick_list::State<Algorithm>,
+  change_trap_list: pick_list::State<TrapShape>,
+  trap_re_slider: slider::State,
+  trap_im_slider: slider::State,
+  trap_radius_slider: slider::State,
+  trap_angle_slider: slider::State,
+  trap_width_slider: slider::State,
+  texture_path_input: text_input::State,
+  texture_path: String,
+  load_texture_button: button::State,
+  clear_texture_button: button::State,
+  change_texture_hit_list: pick_list::State<TextureHit>,
+  stripe_density_slider: slider::State,
+  decomposition_slider: slider::State,
+  angle_strength_slider: slider::State,
+  change_interior_list: pick_list::State<Algorithm>,
+  change_interior_preset_list: pick_list::State<String>,
+  equalize_clip_slider: slider::State,
+  repeat_slider: slider::State,
+  offset_slider: slider::State,
+  density_slider: slider::State,
+  cycle_speed_slider: slider::State,
+  change_lighting_list: pick_list::State<LightingModel>,
+  change_height_source_list: pick_list::State<HeightSource>,
+  azimuth_slider: slider::State,
+  elevation_slider: slider::State,
+  height_slider: slider::State,
+  ambient_slider: slider::State,
+  specular_slider: slider::State,
+  shininess_slider: slider::State,
+  black_level_slider: slider::State,
+  white_level_slider: slider::State,
+  brightness_slider: slider::State,
+  contrast_slider: slider::State,
+  gamma_slider: slider::State,
+  hue_shift_slider: slider::State,
+  saturation_slider: slider::State,
+  sharpen_slider: slider::State,
+  vignette_slider: slider::State,
+  change_dither_list: pick_list::State<Dither>,
+  change_fade_list: pick_list::State<Algorithm>,
+  fade_power_slider: slider::State,
+  change_wrap_list: pick_list::State<GradientWrap>,
+  change_transform_list: pick_list::State<IndexTransform>,
+  change_space_list: pick_list::State<ColorSpace>,
+  change_hue_path_list: pick_list::State<HuePath>,
+  change_curve_list: pick_list::State<GradientCurve>,
+  gradient_editor: GradientEditor,
+  layer_editor: LayerEditor,
+  scroll: scrollable::State,
+}
+
+/// Edits where and how images and raw data are exported. Raw data is loaded
+/// back from `data_path`.
+#[derive(Default)]
+pub struct ExportLayout {
+  directory_input: text_input::State,
+  name_input: text_input::State,
+  change_naming_list: pick_list::State<FileNaming>,
+  change_format_list: pick_list::State<ExportFormat>,
+  quality_slider: slider::State,
+  size_slider: slider::State,
+  change_data_format_list: pick_list::State<DataFormat>,
+  export_data_button: button::State,
+  data_path_input: text_input::State,
+  load_data_button: button::State,
+  data_path: String,
+}
+
+/// Edits the colors of the gradient. The `selected` color is the start color
+/// (`0`), a peak (`1` to the number of peaks) or the end color (the last one).
+#[derive(Default)]
+pub struct GradientEditor {
+  selected: usize,
+  peak_markers: PeakMarkers,
+  previous_peak_button: button::State,
+  next_peak_button: button::State,
+  add_peak_button: button::State,
+  remove_peak_button: button::State,
+  at_slider: slider::State,
+  red_slider: slider::State,
+  green_slider: slider::State,
+  blue_slider: slider::State,
+  alpha_slider: slider::State,
+  hue_slider: slider::State,
+  saturation_slider: slider::State,
+  value_slider: slider::State,
+}
+
+/// Markers below the gradient preview at the positions of the gradient
+/// colors. Clicking a marker selects its color and dragging moves a peak.
+/// The `markers` are the selections of the gradient editor with their
+/// positions, taken from the gradient on every view.
+#[derive(Default)]
+struct PeakMarkers {
+  markers: Vec<(usize, f64)>,
+  selected: usize,
+  peaks: usize,
+  dragging: bool,
+}
+
+/// Edits the layers of the image. The `selected` layer is the one edited.
+#[derive(Default)]
+pub struct LayerEditor {
+  selected: usize,
+  previous_layer_button: button::State,
+  next_layer_button: button::State,
+  add_layer_button: button::State,
+  remove_layer_button: button::State,
+  change_algorithm_list: pick_list::State<Algorithm>,
+  change_blend_list: pick_list::State<BlendMode>,
+  change_mask_list: pick_list::State<LayerMask>,
+  change_gradient_list: pick_list::State<String>,
+  opacity_slider: slider::State,
 }
 
 //==============================================================================
@@ -135,29 +314,148 @@ pub enum FracMsg {
   StepsDown,
   IterationPicked(IterationStyle),
   IteratorPicked(IteratorKind),
+  TrapPicked(TrapShape),
+  ChangeTrapRe(f32),
+  ChangeTrapIm(f32),
+  ChangeTrapRadius(f32),
+  ChangeTrapAngle(f32),
+  ChangeStripeDensity(f32),
   ReUp(f64),
   ReDown(f64),
   ImUp(f64),
   ImDown(f64),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum ImgMsg {
-  ChangeColor,
+  PresetSearchChanged(String),
+  PresetPicked(String),
+  PresetNameChanged(String),
+  SavePreset,
+  RandomizeColors,
+  SeedChanged(String),
+  ApplySeed,
+  SchemePicked(ColorScheme),
+  PalettePathChanged(String),
+  ImportPalette,
+  ExtractPalette,
+  ChangeExtractColors(f32),
+  ExtractOrderPicked(PaletteOrder),
+  ExteriorPicked(Algorithm),
+  ChangeTrapWidth(f32),
+  TexturePathChanged(String),
+  LoadTexture,
+  ClearTexture,
+  TextureHitPicked(TextureHit),
+  ChangeDecomposition(f32),
+  ChangeAngleStrength(f32),
+  InteriorPicked(Algorithm),
+  InteriorPresetPicked(String),
+  ToggleEqualize(bool),
+  ChangeEqualizeClip(f32),
+  ChangeRepeat(f32),
+  ChangeOffset(f32),
+  ChangeDensity(f32),
+  WrapPicked(GradientWrap),
+  TransformPicked(IndexTransform),
+  SpacePicked(ColorSpace),
+  HuePathPicked(HuePath),
+  CurvePicked(GradientCurve),
+  PreviousPeak,
+  NextPeak,
+  AddPeak,
+  RemovePeak,
+  SelectPeak(usize),
+  ChangePeakAt(f32),
+  ChangeChannel(ColorChannel, f32),
+  ToggleSmooth(bool),
+  LightingPicked(LightingModel),
+  HeightSourcePicked(HeightSource),
+  ChangeAzimuth(f32),
+  ChangeElevation(f32),
+  ChangeHeight(f32),
+  ChangeAmbient(f32),
+  ChangeSpecular(f32),
+  ChangeShininess(f32),
+  PreviousLayer,
+  NextLayer,
+  AddLayer,
+  RemoveLayer,
+  LayerAlgorithmPicked(Algorithm),
+  LayerBlendPicked(BlendMode),
+  LayerMaskPicked(LayerMask),
+  LayerGradientPicked(String),
+  ChangeLayerOpacity(f32),
+  ChangeAdjustment(Adjustment, f32),
+  DitherPicked(Dither),
+  ToggleTransparentInterior(bool),
+  ToggleTransparentExterior(bool),
+  ToggleFade(bool),
+  FadePicked(Algorithm),
+  ToggleInvertFade(bool),
+  ChangeFadePower(f32),
+}
+
+/// Channels of the selected gradient color that can be edited.
+#[derive(Debug, Clone, Copy)]
+pub enum ColorChannel {
+  Red,
+  Green,
+  Blue,
+  Alpha,
+  Hue,
+  Saturation,
+  Value,
+}
+
+/// Post-processing adjustments that can be edited.
+#[derive(Debug, Clone, Copy)]
+pub enum Adjustment {
+  BlackLevel,
+  WhiteLevel,
+  Brightness,
+  Contrast,
+  Gamma,
+  HueShift,
+  Saturation,
+  Sharpen,
+  Vignette,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExportMsg {
+  DirectoryChanged(String),
+  NameChanged(String),
+  NamingPicked(FileNaming),
+  FormatPicked(ExportFormat),
+  ChangeQuality(f32),
+  ToggleSixteenBit(bool),
+  ChangeSize(f32),
+  TogglePreviewSize(bool),
+  ImageSaved(std::result::Result<PathBuf, String>),
+  DataFormatPicked(DataFormat),
+  DataPathChanged(String),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum AppMsg {
   SaveImage,
+  ExportData,
+  LoadData,
   ChangeToFracLayout,
   ChangeToColorLayout,
+  ChangeToExportLayout,
   ChangeIncrementSize(f32),
+  ToggleCycling(bool),
+  ChangeCycleSpeed(f32),
+  CycleTick(Instant),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Message {
   Frac(FracMsg),
   Image(ImgMsg),
+  Export(ExportMsg),
   App(AppMsg),
 }
 
@@ -177,6 +475,167 @@ fn button<'a, Message: Clone>(state: &'a mut button::State, label: &str) -> Butt
   .width(Length::Fill)
 }
 
+/// Returns the gradient color selected in the gradient editor.
+fn selected_color(gradient: &Gradient, selected: usize) -> (u8, u8, u8, u8) {
+  if selected == 0 {
+    gradient.start_color
+  } else if selected <= gradient.peaks.len() {
+    gradient.peaks[selected - 1].bgra
+  } else {
+    gradient.end_color
+  }
+}
+
+/// Sets the gradient color selected in the gradient editor to `bgra`.
+fn set_selected_color(gradient: &mut Gradient, selected: usize, bgra: (u8, u8, u8, u8)) {
+  if selected == 0 {
+    gradient.start_color = bgra
+  } else if selected <= gradient.peaks.len() {
+    gradient.peaks[selected - 1].bgra = bgra
+  } else {
+    gradient.end_color = bgra
+  }
+}
+
+/// Returns where the gradient reaches the color selected in the gradient editor.
+fn selected_at(gradient: &Gradient, selected: usize) -> f64 {
+  if selected == 0 {
+    0.
+  } else if selected <= gradient.peaks.len() {
+    gradient.peaks[selected - 1].at
+  } else {
+    1.
+  }
+}
+
+/// Returns the selections of the gradient editor ordered by where the gradient
+/// reaches their colors.
+fn selection_order(gradient: &Gradient) -> Vec<usize> {
+  let mut order: Vec<usize> = (0..gradient.peaks.len() + 2).collect();
+  order.sort_by(|&a, &b| {
+    let (at_a, at_b) = (selected_at(gradient, a), selected_at(gradient, b));
+    at_a.partial_cmp(&at_b).unwrap().then(a.cmp(&b))
+  });
+  order
+}
+
+/// Adds a peak halfway between the selected color and the following one, with
+/// the color the gradient already has there. Returns the selection of the new
+/// peak.
+fn add_peak(gradient: &mut Gradient, selected: usize) -> usize {
+  let at = selected_at(gradient, selected);
+  let (low, high) = if at < 1. {
+    let next = gradient.peaks.iter().map(|peak| peak.at).filter(|&p| p > at).fold(1., f64::min);
+    (at, next)
+  } else {
+    let previous = gradient.peaks.iter().map(|peak| peak.at).filter(|&p| p < at).fold(0., f64::max);
+    (previous, at)
+  };
+  let new_at = (low + high) / 2.;
+
+  // Color of the gradient itself, without repetition and offset
+  let mut plain = gradient.clone();
+  plain.mapping = GradientMapping::default();
+  let bgra = color::gradient_function(plain)(new_at);
+
+  gradient.peaks.push(ColorPeak { bgra: bgra, at: new_at });
+  gradient.peaks.len()
+}
+
+/// Returns `bgra` with `channel` set to `val`. Red, green, blue and alpha go
+/// from 0 to 255, hue from 0 to 360 and saturation and value from 0 to 100.
+fn change_channel(bgra: (u8, u8, u8, u8), channel: ColorChannel, val: f32) -> (u8, u8, u8, u8) {
+  let (b, g, r, a) = bgra;
+  let to_u8 = |val: f32| val.round().max(0.).min(255.) as u8;
+  let mut hsv = space::to_space(ColorSpace::Hsv, bgra);
+  match channel {
+    ColorChannel::Red => (b, g, to_u8(val), a),
+    ColorChannel::Green => (b, to_u8(val), r, a),
+    ColorChannel::Blue => (to_u8(val), g, r, a),
+    ColorChannel::Alpha => (b, g, r, to_u8(val)),
+    ColorChannel::Hue => {
+      hsv[0] = val as f64 / 360.;
+      space::from_space(ColorSpace::Hsv, hsv)
+    }
+    ColorChannel::Saturation => {
+      hsv[1] = val as f64 / 100.;
+      space::from_space(ColorSpace::Hsv, hsv)
+    }
+    ColorChannel::Value => {
+      hsv[2] = val as f64 / 100.;
+      space::from_space(ColorSpace::Hsv, hsv)
+    }
+  }
+}
+
+/// Draws `gradient` as a BGRA strip of size `width * height`.
+fn gradient_strip(gradient: &Gradient, width: u32, height: u32) -> Vec<u8> {
+  let gradient_fun = color::gradient_function(gradient.clone());
+  let row: Vec<u8> = (0..width)
+    .flat_map(|i| {
+      let (b, g, r, a) = gradient_fun(i as f64 / (width - 1) as f64);
+      vec![b, g, r, a]
+    })
+    .collect();
+  row.repeat(height as usize)
+}
+
+/// Sets `adjustment` of `post` to `val`.
+fn change_adjustment(post: &mut PostProcess, adjustment: Adjustment, val: f32) {
+  let val = val as f64;
+  match adjustment {
+    Adjustment::BlackLevel => post.black_level = val,
+    Adjustment::WhiteLevel => post.white_level = val,
+    Adjustment::Brightness => post.brightness = val,
+    Adjustment::Contrast => post.contrast = val,
+    Adjustment::Gamma => post.gamma = val,
+    Adjustment::HueShift => post.hue_shift = val,
+    Adjustment::Saturation => post.saturation = val,
+    Adjustment::Sharpen => post.sharpen = val,
+    Adjustment::Vignette => post.vignette = val,
+  }
+}
+
+/// Creates a slider for changing `adjustment` of the post-processing between
+/// `min` and `max`.
+fn adjustment_slider<'a>(
+  state: &'a mut slider::State,
+  label: &str,
+  min: f32,
+  max: f32,
+  value: f64,
+  adjustment: Adjustment,
+) -> Row<'a, Message> {
+  Row::new()
+    .padding(10)
+    .spacing(10)
+    .push(Slider::new(
+      // adjustments go by 1/100 hence the mess with 100.
+      state,
+      (min * 100.)..=(max * 100.),
+      (value * 100.) as f32,
+      move |val| Message::Image(ImgMsg::ChangeAdjustment(adjustment, val / 100.)),
+    ))
+    .push(Text::new(format!("{}: {:.2}", label, value)).size(24))
+}
+
+/// Creates a slider for changing `channel` of the selected gradient color.
+fn channel_slider<'a>(
+  state: &'a mut slider::State,
+  label: &str,
+  max: f32,
+  value: f32,
+  channel: ColorChannel,
+) -> Row<'a, Message> {
+  Row::new()
+    .padding(10)
+    .spacing(10)
+    .push(Slider::new(state, 0.0..=max, value, move |val| {
+      Message::Image(ImgMsg::ChangeChannel(channel, val))
+    }))
+    .push(Text::new(format!("{}: {:.0}", label, value)).size(24))
+}
+
 pub enum ButtonStyle {
   Primary,
   // Secondary,
@@ -212,37 +671,94 @@ impl button::StyleSheet for ButtonStyle {
 // Main Window
 
 impl MainWindow {
-  fn redraw_frac(&mut self, redraw_image: bool) {
-    self.frac_state.fractal = draw::compute_fractal(self.frac_state.args);
+  /// Computes the fractal anew. Fails if it would not fit into memory with the
+  /// current coloring, in which case the previous fractal is kept.
+  fn redraw_frac(&mut self, redraw_image: bool) -> Result<()> {
+    draw::check_memory(self.frac_state.args.field.pixel_size, &self.image_state.args)?;
+    self.frac_state.fractal = Arc::new(draw::compute_fractal(self.frac_state.args));
     if redraw_image {
       self.redraw_only_image()
     }
+    Ok(())
+  }
+
+  /// Replaces the gradient with the one generated from `seed`, keeping the
+  /// mapping. Does not redraw the image.
+  fn apply_seed(&mut self, seed: GradientSeed) {
+    let mut gradient = random::random_gradient(seed);
+    gradient.mapping = self.image_state.args.gradient.mapping;
+    self.image_state.args.gradient = gradient;
+    self.image_state.args.seed = Some(seed);
+    self.color_layout.seed_text = seed.seed.to_string();
+    self.color_layout.gradient_editor.selected = 0
   }
 
   fn redraw_only_image(&mut self) {
+    if let Err(err) = self.try_redraw_image() {
+      self.status = format!("could not color fractal: {}", err)
+    }
+  }
+
+  /// Colors the stored fractal and shows it. Fails if the coloring would not
+  /// fit into memory, in which case the shown image stays.
+  fn try_redraw_image(&mut self) -> Result<()> {
     let frac = &self.frac_state.fractal;
-    let gradient = self.image_state.args.gradient.clone();
-    let image = color::color_fractal(frac, self.frac_state.args.steps, gradient);
+    let frac_args = &self.frac_state.args;
+    let img_args = &self.image_state.args;
+    let hits = &mut self.frac_state.texture_hits;
+    let image = render::color_image(frac, frac_args, img_args, hits)?;
+    // Earlier failures no longer apply once the image is shown
+    self.status.clear();
     self.image_state.image = image.clone();
     let pix_size = self.frac_state.args.field.pixel_size;
     self.displayed_image = color::resize_fractal_image(&image, pix_size, 1000);
+    // The cycle has to start over from the new coloring
+    if self.app_state.cycling {
+      self.cycle = Some(PaletteCycle::new(frac, frac_args, img_args, hits))
+    }
+    Ok(())
+  }
+
+  /// Recolors the stored fractal from the palette cycle, which is much faster
+  /// than `redraw_only_image` as only the gradients may have changed.
+  fn redraw_cycle_frame(&mut self) {
+    let frac = &self.frac_state.fractal;
+    let frac_args = &self.frac_state.args;
+    let img_args = &self.image_state.args;
+    if let Some(ref cycle) = self.cycle {
+      let image = cycle.color(frac, frac_args, img_args);
+      let pix_size = frac_args.field.pixel_size;
+      self.displayed_image = color::resize_fractal_image(&image, pix_size, 1000);
+      self.image_state.image = image;
+    }
   }
 }
 
-impl Sandbox for MainWindow {
+impl Application for MainWindow {
+  type Executor = executor::Default;
   type Message = Message;
+  type Flags = ();
 
-  fn new() -> Self {
-    Self::default()
+  fn new(_flags: ()) -> (Self, Command<Message>) {
+    (Self::default(), Command::none())
   }
 
   fn title(&self) -> String {
     String::from("Fractalisator")
   }
 
-  fn update(&mut self, message: Message) {
+  fn subscription(&self) -> Subscription<Message> {
+    if self.app_state.cycling {
+      time::every(CYCLE_FRAME).map(|now| Message::App(AppMsg::CycleTick(now)))
+    } else {
+      Subscription::none()
+    }
+  }
+
+  fn update(&mut self, message: Message) -> Command<Message> {
     match message {
       Message::Frac(fm) => {
+        let previous_args = self.frac_state.args;
         match fm {
           FracMsg::ZoomIn => {
             self.frac_state.args.field.radius *= 0.8;
@@ -280,6 +796,12 @@ impl Sandbox for MainWindow {
           }
           FracMsg::IterationPicked(kind) => self.frac_state.args.iteration_style = kind,
           FracMsg::IteratorPicked(kind) => self.frac_state.args.iterator_kind = kind,
+          FracMsg::TrapPicked(shape) => self.frac_state.args.trap.shape = shape,
+          FracMsg::ChangeTrapRe(val) => self.frac_state.args.trap.re = val as f64,
+          FracMsg::ChangeTrapIm(val) => self.frac_state.args.trap.im = val as f64,
+          FracMsg::ChangeTrapRadius(val) => self.frac_state.args.trap.radius = val as f64,
+          FracMsg::ChangeTrapAngle(val) => self.frac_state.args.trap.angle = (val as f64).to_radians(),
+          FracMsg::ChangeStripeDensity(val) => self.frac_state.args.stripe_density = val as f64,
           FracMsg::ReUp(log_incr) => self.frac_state.args.c_re += (2.0 as f64).powf(log_incr),
           FracMsg::ReDown(log_incr) => self.frac_state.args.c_re -= (2.0 as f64).powf(log_incr),
           FracMsg::ImUp(log_incr) => self.frac_state.args.c_im += (2.0 as f64).powf(log_incr),
@@ -287,47 +809,408 @@ impl Sandbox for MainWindow {
         };
         // Since the fractal arguments changed, we have to update the raw and
         // colored fractal
-        self.redraw_frac(true)
+        self.frac_state.args.find_cycles = self.image_state.args.needs_cycles();
+        if let Err(err) = self.redraw_frac(true) {
+          // The shown fractal still belongs to the previous arguments
+          self.frac_state.args = previous_args;
+          self.status = format!("could not draw fractal: {}", err)
+        }
       }
       Message::Image(im) => {
+        // The image is redrawn after the match, messages that leave it as it
+        // is return early
         match im {
-          ImgMsg::ChangeColor => match self.image_state.current_preset {
-            GradientPreset::Azul => {
-              self.image_state.args.gradient = Gradient::svarog();
-              self.image_state.current_preset = GradientPreset::Svarog
+          ImgMsg::PresetSearchChanged(query) => {
+            self.color_layout.preset_search = query;
+            return Command::none();
+          }
+          ImgMsg::PresetPicked(name) => {
+            if let Some(preset) = self.image_state.presets.get(&name) {
+              self.image_state.args.gradient = preset.clone();
+              self.image_state.args.seed = None;
+              self.image_state.current_preset = name;
+              self.color_layout.gradient_editor.selected = 0
+            }
+          }
+          ImgMsg::PresetNameChanged(name) => {
+            self.color_layout.preset_name = name;
+            return Command::none();
+          }
+          ImgMsg::SavePreset => {
+            // Saving does not change the image
+            let name = self.color_layout.preset_name.trim().to_string();
+            let gradient = &self.image_state.args.gradient;
+            self.color_layout.palette_status = match self.image_state.presets.save(&name, gradient) {
+              Ok(()) => {
+                self.image_state.current_preset = name.clone();
+                format!("Saved preset {}", name)
+              }
+              Err(err) => err.to_string(),
+            };
+            return Command::none();
+          }
+          ImgMsg::PalettePathChanged(path) => {
+            self.color_layout.palette_path = path;
+            return Command::none();
+          }
+          ImgMsg::ImportPalette => {
+            let path = self.color_layout.palette_path.clone();
+            match palette::load_palette(&path) {
+              Ok(mut gradient) => {
+                gradient.mapping = self.image_state.args.gradient.mapping;
+                self.image_state.args.gradient = gradient;
+                self.image_state.args.seed = None;
+                self.color_layout.gradient_editor.selected = 0;
+                self.color_layout.palette_status = format!("Imported {}", path)
+              }
+              Err(err) => {
+                self.color_layout.palette_status = err.to_string();
+                return Command::none();
+              }
             }
-            GradientPreset::Svarog => {
-              self.image_state.args.gradient = Gradient::emperor();
-              self.image_state.current_preset = GradientPreset::Emperor
+          }
+          ImgMsg::RandomizeColors => {
+            let seed = random::new_seed(self.image_state.random_scheme);
+            self.apply_seed(seed)
+          }
+          ImgMsg::SeedChanged(text) => {
+            self.color_layout.seed_text = text;
+            return Command::none();
+          }
+          ImgMsg::ApplySeed | ImgMsg::SchemePicked(_) => {
+            if let ImgMsg::SchemePicked(scheme) = im {
+              // Without a seed there is no gradient to regenerate yet
+              self.image_state.random_scheme = scheme;
+              if self.color_layout.seed_text.trim().is_empty() {
+                return Command::none();
+              }
             }
-            GradientPreset::Emperor => {
-              self.image_state.args.gradient = Gradient::gaia();
-              self.image_state.current_preset = GradientPreset::Gaia
+            match self.color_layout.seed_text.trim().parse::<u32>() {
+              Ok(seed) => {
+                let scheme = self.image_state.random_scheme;
+                self.apply_seed(GradientSeed{scheme: scheme, seed: seed})
+              }
+              Err(_) => {
+                self.color_layout.palette_status = format!("'{}' is not a seed", self.color_layout.seed_text);
+                return Command::none();
+              }
             }
-            GradientPreset::Gaia => {
-              self.image_state.args.gradient = Gradient::azul();
-              self.image_state.current_preset = GradientPreset::Azul
+          }
+          ImgMsg::ExtractPalette => {
+            let path = self.color_layout.palette_path.clone();
+            let colors = self.image_state.extract_colors;
+            match extract::load_photo_palette(&path, colors, self.image_state.extract_order) {
+              Ok(mut gradient) => {
+                gradient.mapping = self.image_state.args.gradient.mapping;
+                self.image_state.args.gradient = gradient;
+                self.image_state.args.seed = None;
+                self.color_layout.gradient_editor.selected = 0;
+                self.color_layout.palette_status = format!("Extracted colors from {}", path)
+              }
+              Err(err) => {
+                self.color_layout.palette_status = format!("could not open photo: {}", err);
+                return Command::none();
+              }
             }
-          },
+          }
+          ImgMsg::ChangeExtractColors(val) => {
+            // Only affects the next extraction
+            self.image_state.extract_colors = val as usize;
+            return Command::none();
+          }
+          ImgMsg::ExtractOrderPicked(order) => {
+            self.image_state.extract_order = order;
+            return Command::none();
+          }
+          ImgMsg::ExteriorPicked(algorithm) => self.image_state.args.exterior = algorithm,
+          ImgMsg::ChangeTrapWidth(val) => self.image_state.args.trap_width = val as f64,
+          ImgMsg::TexturePathChanged(path) => {
+            self.color_layout.texture_path = path;
+            return Command::none();
+          }
+          ImgMsg::LoadTexture => {
+            // The texture follows the orbit trap wherever it is moved
+            let path = &self.color_layout.texture_path;
+            match texture::load_texture(path) {
+              Ok(texture) => self.image_state.args.texture = Some(texture),
+              Err(err) => {
+                // The texture loaded before stays in place
+                self.status = format!("could not load texture: {}", err);
+                return Command::none();
+              }
+            }
+          }
+          ImgMsg::ClearTexture => self.image_state.args.texture = None,
+          ImgMsg::TextureHitPicked(hit) => self.image_state.args.texture_hit = hit,
+          ImgMsg::ChangeDecomposition(val) => self.image_state.args.decomposition = val as usize,
+          ImgMsg::ChangeAngleStrength(val) => self.image_state.args.angle_strength = val as f64,
+          ImgMsg::InteriorPicked(algorithm) => self.image_state.args.interior = algorithm,
+          ImgMsg::InteriorPresetPicked(name) => {
+            if let Some(preset) = self.image_state.presets.get(&name) {
+              self.image_state.args.interior_gradient = preset.clone();
+              self.image_state.current_interior_preset = name
+            }
+          }
+          ImgMsg::ToggleEqualize(equalize) => self.image_state.args.equalize = equalize,
+          ImgMsg::ChangeEqualizeClip(val) => self.image_state.args.equalize_clip = val as f64,
+          ImgMsg::ChangeRepeat(val) => self.image_state.args.gradient.mapping.repeat = val as f64,
+          ImgMsg::ChangeOffset(val) => self.image_state.args.gradient.mapping.offset = val as f64,
+          ImgMsg::ChangeDensity(val) => self.image_state.args.gradient.mapping.density = val as f64,
+          ImgMsg::WrapPicked(wrap) => self.image_state.args.gradient.mapping.wrap = wrap,
+          ImgMsg::TransformPicked(transform) => {
+            self.image_state.args.gradient.mapping.transform = transform
+          }
+          ImgMsg::SpacePicked(space) => {
+            self.image_state.args.gradient.blend.space = space;
+            self.image_state.args.seed = None
+          }
+          ImgMsg::HuePathPicked(path) => {
+            self.image_state.args.gradient.blend.hue_path = path;
+            self.image_state.args.seed = None
+          }
+          ImgMsg::CurvePicked(curve) => {
+            self.image_state.args.gradient.blend.curve = curve;
+            self.image_state.args.seed = None
+          }
+          ImgMsg::PreviousPeak | ImgMsg::NextPeak => {
+            let gradient = &self.image_state.args.gradient;
+            let order = selection_order(gradient);
+            let selected = self.color_layout.gradient_editor.selected;
+            let pos = order.iter().position(|&i| i == selected).unwrap_or(0);
+            let pos = match im {
+              ImgMsg::PreviousPeak => pos.saturating_sub(1),
+              _ => (pos + 1).min(order.len() - 1),
+            };
+            self.color_layout.gradient_editor.selected = order[pos];
+            return Command::none();
+          }
+          ImgMsg::SelectPeak(selected) => {
+            self.color_layout.gradient_editor.selected = selected;
+            return Command::none();
+          }
+          ImgMsg::AddPeak => {
+            let selected = self.color_layout.gradient_editor.selected;
+            let gradient = &mut self.image_state.args.gradient;
+            self.color_layout.gradient_editor.selected = add_peak(gradient, selected);
+            self.image_state.args.seed = None
+          }
+          ImgMsg::RemovePeak => {
+            let selected = self.color_layout.gradient_editor.selected;
+            let gradient = &mut self.image_state.args.gradient;
+            if selected > 0 && selected <= gradient.peaks.len() {
+              gradient.peaks.remove(selected - 1);
+              self.image_state.args.seed = None;
+              self.color_layout.gradient_editor.selected = selected - 1
+            }
+          }
+          ImgMsg::ChangePeakAt(val) => {
+            let selected = self.color_layout.gradient_editor.selected;
+            let gradient = &mut self.image_state.args.gradient;
+            if selected > 0 && selected <= gradient.peaks.len() {
+              gradient.peaks[selected - 1].at = val as f64;
+              self.image_state.args.seed = None
+            }
+          }
+          ImgMsg::ChangeChannel(channel, val) => {
+            let selected = self.color_layout.gradient_editor.selected;
+            let gradient = &mut self.image_state.args.gradient;
+            let bgra = change_channel(selected_color(gradient, selected), channel, val);
+            set_selected_color(gradient, selected, bgra);
+            self.image_state.args.seed = None
+          }
+          ImgMsg::ToggleSmooth(smooth) => {
+            self.image_state.args.gradient.smooth = smooth;
+            self.image_state.args.seed = None
+          }
+          ImgMsg::LightingPicked(model) => self.image_state.args.lighting.model = model,
+          ImgMsg::HeightSourcePicked(source) => self.image_state.args.lighting.source = source,
+          ImgMsg::ChangeAzimuth(val) => self.image_state.args.lighting.azimuth = val as f64,
+          ImgMsg::ChangeElevation(val) => self.image_state.args.lighting.elevation = val as f64,
+          ImgMsg::ChangeHeight(val) => self.image_state.args.lighting.height = val as f64,
+          ImgMsg::ChangeAmbient(val) => self.image_state.args.lighting.ambient = val as f64,
+          ImgMsg::ChangeSpecular(val) => self.image_state.args.lighting.specular = val as f64,
+          ImgMsg::ChangeShininess(val) => self.image_state.args.lighting.shininess = val as f64,
+          ImgMsg::ChangeAdjustment(adjustment, val) =>
+            change_adjustment(&mut self.image_state.args.post, adjustment, val),
+          ImgMsg::DitherPicked(dither) => self.image_state.args.post.dither = dither,
+          ImgMsg::ToggleTransparentInterior(transparent) =>
+            self.image_state.args.transparency.interior = transparent,
+          ImgMsg::ToggleTransparentExterior(transparent) =>
+            self.image_state.args.transparency.exterior = transparent,
+          ImgMsg::ToggleFade(fade) => self.image_state.args.transparency.fade = fade,
+          ImgMsg::FadePicked(algorithm) =>
+            self.image_state.args.transparency.fade_algorithm = algorithm,
+          ImgMsg::ToggleInvertFade(invert) =>
+            self.image_state.args.transparency.invert_fade = invert,
+          ImgMsg::ChangeFadePower(val) =>
+            self.image_state.args.transparency.fade_power = val as f64,
+          ImgMsg::PreviousLayer | ImgMsg::NextLayer => {
+            let count = self.image_state.args.layers.len();
+            let editor = &mut self.color_layout.layer_editor;
+            editor.selected = match im {
+              ImgMsg::PreviousLayer => editor.selected.saturating_sub(1),
+              _ => (editor.selected + 1).min(count.saturating_sub(1)),
+            };
+            return Command::none();
+          }
+          ImgMsg::AddLayer => {
+            // New layers go on top
+            let layers = &mut self.image_state.args.layers;
+            layers.push(Layer::default());
+            self.color_layout.layer_editor.selected = layers.len() - 1
+          }
+          ImgMsg::RemoveLayer => {
+            let selected = self.color_layout.layer_editor.selected;
+            let layers = &mut self.image_state.args.layers;
+            if selected < layers.len() {
+              layers.remove(selected);
+              self.color_layout.layer_editor.selected = selected.min(layers.len().saturating_sub(1))
+            }
+          }
+          ImgMsg::LayerAlgorithmPicked(_)
+          | ImgMsg::LayerBlendPicked(_)
+          | ImgMsg::LayerMaskPicked(_)
+          | ImgMsg::LayerGradientPicked(_)
+          | ImgMsg::ChangeLayerOpacity(_) => {
+            let selected = self.color_layout.layer_editor.selected;
+            let presets = &self.image_state.presets;
+            let layer = match self.image_state.args.layers.get_mut(selected) {
+              Some(layer) => layer,
+              None => return Command::none(),
+            };
+            match im {
+              ImgMsg::LayerAlgorithmPicked(algorithm) => layer.algorithm = algorithm,
+              ImgMsg::LayerBlendPicked(blend) => layer.blend = blend,
+              ImgMsg::LayerMaskPicked(mask) => layer.mask = mask,
+              ImgMsg::LayerGradientPicked(name) => {
+                if let Some(gradient) = presets.get(&name) {
+                  layer.gradient = gradient.clone()
+                }
+              }
+              ImgMsg::ChangeLayerOpacity(val) => layer.opacity = val as f64,
+              _ => {}
+            }
+          }
         };
-        self.redraw_only_image()
+        // Attracting cycles are only searched for once a coloring needs them
+        if self.image_state.args.needs_cycles() && !self.frac_state.args.find_cycles {
+          self.frac_state.args.find_cycles = true;
+          if let Err(err) = self.redraw_frac(true) {
+            self.frac_state.args.find_cycles = false;
+            self.status = format!("could not draw fractal: {}", err)
+          }
+        } else {
+          self.redraw_only_image()
+        }
+      }
+      Message::Export(em) => {
+        let export = &mut self.image_state.export;
+        match em {
+          ExportMsg::DirectoryChanged(directory) => export.directory = directory,
+          ExportMsg::NameChanged(name) => export.name = name,
+          ExportMsg::NamingPicked(naming) => export.naming = naming,
+          ExportMsg::FormatPicked(format) => export.format = format,
+          ExportMsg::ChangeQuality(val) => export.jpeg_quality = val as u8,
+          ExportMsg::ToggleSixteenBit(sixteen_bit) => export.sixteen_bit = sixteen_bit,
+          ExportMsg::ChangeSize(val) => export.pixel_size = val as usize,
+          ExportMsg::TogglePreviewSize(at_preview) => export.at_preview = at_preview,
+          ExportMsg::ImageSaved(saved) => {
+            self.status = match saved {
+              Ok(path) => format!("Saved {}", path.display()),
+              Err(err) => format!("could not save image: {}", err),
+            }
+          }
+          ExportMsg::DataFormatPicked(format) => export.data_format = format,
+          ExportMsg::DataPathChanged(path) => self.export_layout.data_path = path,
+        }
       }
       Message::App(ap) => match ap {
         AppMsg::ChangeIncrementSize(val) => self.app_state.log_increment_size = val as f64,
         AppMsg::ChangeToColorLayout => self.app_state.layout = Layout::ColorOptions,
         AppMsg::ChangeToFracLayout => self.app_state.layout = Layout::FracOptions,
+        AppMsg::ChangeToExportLayout => self.app_state.layout = Layout::ExportOptions,
         AppMsg::SaveImage => {
-          imagetool::ImageBuffer::<imagetool::Rgba<u8>, Vec<u8>>::from_vec(
-            self.frac_state.args.field.pixel_size as u32,
-            self.frac_state.args.field.pixel_size as u32,
-            color::bgra_to_rgba(&self.image_state.image),
-          )
-          .unwrap()
-          .save("fractal_image.png")
-          .unwrap()
+          let export = self.image_state.export.clone();
+          let frac_args = self.frac_state.args;
+          let img_args = self.image_state.args.clone();
+          // At the resolution of the preview the shown fractal is colored again
+          let fractal =
+            if export.at_preview || export.pixel_size == frac_args.field.pixel_size {
+              Some(Arc::clone(&self.frac_state.fractal))
+            } else {
+              None
+            };
+          // Rendering takes a while, so the image is saved in the background
+          self.status = "Saving image...".to_string();
+          return Command::perform(
+            future::lazy(move |_| {
+              let saved = match fractal {
+                Some(fractal) => export::export_fractal(&fractal, &frac_args, &img_args, &export),
+                None => export::export_image(&frac_args, &img_args, &export),
+              };
+              saved.map_err(|err| err.to_string())
+            }),
+            |saved| Message::Export(ExportMsg::ImageSaved(saved)),
+          );
+        },
+        AppMsg::ExportData => {
+          let export = &self.image_state.export;
+          let saved = export::export_data(&self.frac_state.fractal, &self.frac_state.args, export);
+          self.status = match saved {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(err) => format!("could not save data: {}", err),
+          }
+        },
+        AppMsg::LoadData => {
+          let path = self.export_layout.data_path.clone();
+          match raw::load_raw(&path, &self.image_state.args) {
+            Ok((args, fractal)) => {
+              // The loaded fractal is only recolored, never computed again
+              let has_cycles = args.find_cycles;
+              let previous_args = std::mem::replace(&mut self.frac_state.args, args);
+              let previous_fractal = std::mem::replace(&mut self.frac_state.fractal, Arc::new(fractal));
+              self.status = match self.try_redraw_image() {
+                Ok(()) if !has_cycles && self.image_state.args.needs_cycles() =>
+                  format!("Loaded {}, without the attracting cycles the coloring needs", path),
+                Ok(()) => format!("Loaded {}", path),
+                Err(err) => {
+                  // The shown image still belongs to the previous fractal
+                  self.frac_state.args = previous_args;
+                  self.frac_state.fractal = previous_fractal;
+                  format!("could not color loaded data: {}", err)
+                }
+              }
+            }
+            Err(err) => self.status = format!("could not load data: {}", err),
+          }
         },
+        AppMsg::ToggleCycling(cycling) => {
+          self.app_state.cycling = cycling;
+          self.app_state.last_tick = None;
+          self.cycle = None;
+          if cycling {
+            let frac = &self.frac_state.fractal;
+            let frac_args = &self.frac_state.args;
+            let img_args = &self.image_state.args;
+            let hits = &mut self.frac_state.texture_hits;
+            self.cycle = Some(PaletteCycle::new(frac, frac_args, img_args, hits))
+          }
+        }
+        AppMsg::ChangeCycleSpeed(val) => self.app_state.cycle_speed = val as f64,
+        AppMsg::CycleTick(now) => {
+          let elapsed = match self.app_state.last_tick {
+            Some(last) => now.duration_since(last).as_secs_f64(),
+            None => 0.,
+          };
+          self.app_state.last_tick = Some(now);
+          let mapping = &mut self.image_state.args.gradient.mapping;
+          mapping.offset = (mapping.offset + self.app_state.cycle_speed * elapsed).rem_euclid(1.);
+          self.image_state.args.seed = None;
+          self.redraw_cycle_frame()
+        }
       },
     }
+    Command::none()
   }
 
   fn view(&mut self) -> Element<Message> {
@@ -335,15 +1218,22 @@ impl Sandbox for MainWindow {
     let image = Image::new(image_handle)
       .width(Length::Units(1000))
       .height(Length::Units(1000));
-    let layout = match self.app_state.layout {
+    let layout: Element<Message> = match self.app_state.layout {
       Layout::FracOptions => self
         .frac_layout
         .view(self.frac_state.args, self.app_state.log_increment_size)
-        .width(Length::Units(500)),
+        .width(Length::Units(500))
+        .into(),
       Layout::ColorOptions => self
         .color_layout
-        .view(self.frac_state.args)
-        .width(Length::Units(500)),
+        .view(self.frac_state.args, &self.image_state, &self.app_state)
+        .width(Length::Units(500))
+        .into(),
+      Layout::ExportOptions => self
+        .export_layout
+        .view(&self.image_state.export, self.frac_state.args.field.pixel_size)
+        .width(Length::Units(500))
+        .into(),
     };
     let layout_buttons = Row::new()
       .padding(10)
@@ -358,13 +1248,18 @@ impl Sandbox for MainWindow {
         button(&mut self.to_color_layout_button, "Color Options")
           .on_press(Message::App(AppMsg::ChangeToColorLayout))
           .style(ButtonStyle::Primary),
+      )
+      .push(
+        button(&mut self.to_export_layout_button, "Export Options")
+          .on_press(Message::App(AppMsg::ChangeToExportLayout))
+          .style(ButtonStyle::Primary),
       );
     let save_button = Row::new()
       .padding(10)
       .spacing(10)
       .width(Length::Units(500))
       .push(
-        button(&mut self.save_button, "Save Fractal")
+        button(&mut self.save_button, "Export Fractal")
           .on_press(Message::App(AppMsg::SaveImage))
           .style(ButtonStyle::Primary),
       );
@@ -378,7 +1273,8 @@ impl Sandbox for MainWindow {
           .spacing(10)
           .push(layout_buttons)
           .push(layout)
-          .push(save_button),
+          .push(save_button)
+          .push(Text::new(&self.status).size(20)),
       )
       .push(Column::new().padding(10).spacing(10).push(image))
       .into()
@@ -562,11 +1458,25 @@ impl<'a> FractalAdjustmentButtons {
 // -----------------------------------------------------------------------------
 
 impl<'a> ColorLayout {
-  fn view(&'a mut self, frac_args: FracArgs) -> Column<'a, Message> {
+  fn view(
+    &'a mut self,
+    frac_args: FracArgs,
+    image_state: &ImageState,
+    app_state: &AppState,
+  ) -> Scrollable<'a, Message> {
     let row_space = 10;
     let row_pad = 10;
+    let img_args = &image_state.args;
+    let lighting = img_args.lighting;
+    let post = img_args.post;
+    let transparency = img_args.transparency;
+    let layer_presets = image_state.presets.search("");
+    let found_presets = image_state.presets.search(&self.preset_search);
+    let all_presets = image_state.presets.search("");
+    let mapping = img_args.gradient.mapping;
+    let blend = img_args.gradient.blend;
 
-    Column::new()
+    let options = Column::new()
       .padding(10)
       .spacing(10)
       .push(self.image_adjustment_buttons.view())
@@ -583,50 +1493,1133 @@ impl<'a> ColorLayout {
               .on_press(Message::Frac(FracMsg::StepsDown)),
           ),
       )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.preset_search_input,
+              "Search presets",
+              &self.preset_search,
+              |query| Message::Image(ImgMsg::PresetSearchChanged(query)),
+            )
+            .padding(8)
+            .size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_preset_list,
+              found_presets,
+              Some(image_state.current_preset.clone()),
+              |name| Message::Image(ImgMsg::PresetPicked(name)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.preset_name_input,
+              "Preset name",
+              &self.preset_name,
+              |name| Message::Image(ImgMsg::PresetNameChanged(name)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::Image(ImgMsg::SavePreset)),
+          )
+          .push(
+            button(&mut self.save_preset_button, "Save Preset")
+              .on_press(Message::Image(ImgMsg::SavePreset)),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            button(&mut self.randomize_button, "Randomize Colors")
+              .on_press(Message::Image(ImgMsg::RandomizeColors)),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_scheme_list,
+              definitions::ALL_COLOR_SCHEMES,
+              Some(image_state.random_scheme),
+              |scheme| Message::Image(ImgMsg::SchemePicked(scheme)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            TextInput::new(
+              &mut self.seed_input,
+              "Seed",
+              &self.seed_text,
+              |text| Message::Image(ImgMsg::SeedChanged(text)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::Image(ImgMsg::ApplySeed)),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.palette_path_input,
+              "palette.ggr, .gpl, .map, .ugr, .fgr or a photo",
+              &self.palette_path,
+              |path| Message::Image(ImgMsg::PalettePathChanged(path)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::Image(ImgMsg::ImportPalette)),
+          )
+          .push(
+            button(&mut self.import_palette_button, "Import Palette")
+              .on_press(Message::Image(ImgMsg::ImportPalette)),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            button(&mut self.extract_palette_button, "Extract From Photo")
+              .on_press(Message::Image(ImgMsg::ExtractPalette)),
+          )
+          .push(Slider::new(
+            &mut self.extract_colors_slider,
+            2.0..=16.0,
+            image_state.extract_colors as f32,
+            |val| Message::Image(ImgMsg::ChangeExtractColors(val)),
+          ))
+          .push(Text::new(format!("colors: {}", image_state.extract_colors)).size(24))
+          .push(
+            PickList::new(
+              &mut self.change_extract_order_list,
+              definitions::ALL_PALETTE_ORDERS,
+              Some(image_state.extract_order),
+              |order| Message::Image(ImgMsg::ExtractOrderPicked(order)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(Text::new(&self.palette_status).size(20))
+      .push(self.gradient_editor.view(&img_args.gradient))
+      .push(
+        Row::new().padding(row_pad).spacing(row_space).push(
+          PickList::new(
+            &mut self.change_exterior_list,
+            algorithms::EXTERIOR_ALGORITHMS,
+            Some(img_args.exterior),
+            |algorithm| Message::Image(ImgMsg::ExteriorPicked(algorithm)),
+          )
+          .padding(8)
+          .text_size(24),
+        ),
+      )
       .push(
         Row::new().padding(row_pad).spacing(row_space).push(
-          button(&mut self.change_color_button, "Change Color")
-            .on_press(Message::Image(ImgMsg::ChangeColor)),
+          PickList::new(
+            &mut self.change_trap_list,
+            definitions::ALL_TRAP_SHAPES,
+            Some(frac_args.trap.shape),
+            |shape| Message::Frac(FracMsg::TrapPicked(shape)),
+          )
+          .padding(8)
+          .text_size(24),
         ),
       )
       .push(
         Row::new()
           .padding(row_pad)
           .spacing(row_space)
-          .push(Text::new(format!(
-            "Constant: {:.10} + {:.10} i",
-            frac_args.c_re, frac_args.c_im
-          ))),
+          .push(Slider::new(
+            // positions go by 1/100 hence the mess with 100.
+            &mut self.trap_re_slider,
+            -200.0..=200.0,
+            (frac_args.trap.re * 100.) as f32,
+            |val| Message::Frac(FracMsg::ChangeTrapRe(val / 100.)),
+          ))
+          .push(Text::new(format!("trap re: {:.2}", frac_args.trap.re)).size(24)),
       )
       .push(
         Row::new()
           .padding(row_pad)
           .spacing(row_space)
-          .push(Text::new(format!(
-            "Center: {:.10} + {:.10} i",
-            frac_args.field.center_im, frac_args.field.center_re
-          ))),
+          .push(Slider::new(
+            // positions go by 1/100 hence the mess with 100.
+            &mut self.trap_im_slider,
+            -200.0..=200.0,
+            (frac_args.trap.im * 100.) as f32,
+            |val| Message::Frac(FracMsg::ChangeTrapIm(val / 100.)),
+          ))
+          .push(Text::new(format!("trap im: {:.2}", frac_args.trap.im)).size(24)),
       )
       .push(
         Row::new()
           .padding(row_pad)
           .spacing(row_space)
-          .push(Text::new(format!("Radius: {:.10}", frac_args.field.radius))),
+          .push(Slider::new(
+            // radii go by 1/100 hence the mess with 100.
+            &mut self.trap_radius_slider,
+            1.0..=200.0,
+            (frac_args.trap.radius * 100.) as f32,
+            |val| Message::Frac(FracMsg::ChangeTrapRadius(val / 100.)),
+          ))
+          .push(Text::new(format!("trap radius: {:.2}", frac_args.trap.radius)).size(24)),
       )
       .push(
         Row::new()
           .padding(row_pad)
           .spacing(row_space)
-          .push(Text::new(format!("Steps: {}", frac_args.steps))),
+          .push(Slider::new(
+            &mut self.trap_angle_slider,
+            0.0..=180.0,
+            frac_args.trap.angle.to_degrees() as f32,
+            |val| Message::Frac(FracMsg::ChangeTrapAngle(val)),
+          ))
+          .push(Text::new(format!("trap angle: {:.0}°", frac_args.trap.angle.to_degrees())).size(24)),
       )
       .push(
         Row::new()
           .padding(row_pad)
           .spacing(row_space)
-          .push(Text::new(format!(
-            "Pixel width: {}",
-            frac_args.field.pixel_size
-          ))),
+          .push(Slider::new(
+            // widths go by 1/25 hence the mess with 25.
+            &mut self.trap_width_slider,
+            1.0..=100.0,
+            (img_args.trap_width * 25.) as f32,
+            |val| Message::Image(ImgMsg::ChangeTrapWidth(val / 25.)),
+          ))
+          .push(Text::new(format!("trap width: {:.2}", img_args.trap_width)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.texture_path_input,
+              "texture.png",
+              &self.texture_path,
+              |path| Message::Image(ImgMsg::TexturePathChanged(path)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::Image(ImgMsg::LoadTexture)),
+          )
+          .push(
+            button(&mut self.load_texture_button, "Load Texture")
+              .on_press(Message::Image(ImgMsg::LoadTexture)),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            button(&mut self.clear_texture_button, "Clear Texture")
+              .on_press(Message::Image(ImgMsg::ClearTexture)),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_texture_hit_list,
+              definitions::ALL_TEXTURE_HITS,
+              Some(img_args.texture_hit),
+              |hit| Message::Image(ImgMsg::TextureHitPicked(hit)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            &mut self.stripe_density_slider,
+            1.0..=20.0,
+            frac_args.stripe_density as f32,
+            |val| Message::Frac(FracMsg::ChangeStripeDensity(val)),
+          ))
+          .push(Text::new(format!("stripe density: {}", frac_args.stripe_density)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            &mut self.decomposition_slider,
+            2.0..=16.0,
+            img_args.decomposition as f32,
+            |val| Message::Image(ImgMsg::ChangeDecomposition(val)),
+          ))
+          .push(Text::new(format!("sectors: {}", img_args.decomposition)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // strengths go by 1/20 hence the mess with 20.
+            &mut self.angle_strength_slider,
+            0.0..=20.0,
+            (img_args.angle_strength * 20.) as f32,
+            |val| Message::Image(ImgMsg::ChangeAngleStrength(val / 20.)),
+          ))
+          .push(Text::new(format!("angle strength: {:.2}", img_args.angle_strength)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_interior_list,
+              algorithms::INTERIOR_ALGORITHMS,
+              Some(img_args.interior),
+              |algorithm| Message::Image(ImgMsg::InteriorPicked(algorithm)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_interior_preset_list,
+              all_presets,
+              Some(image_state.current_interior_preset.clone()),
+              |name| Message::Image(ImgMsg::InteriorPresetPicked(name)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(img_args.equalize, "Equalize", |equalize| {
+            Message::Image(ImgMsg::ToggleEqualize(equalize))
+          }))
+          .push(Slider::new(
+            // clips go by 1/200 hence the mess with 200.
+            &mut self.equalize_clip_slider,
+            0.0..=20.0,
+            (img_args.equalize_clip * 200.) as f32,
+            |val| Message::Image(ImgMsg::ChangeEqualizeClip(val / 200.)),
+          ))
+          .push(Text::new(format!("clip: {:.1}%", img_args.equalize_clip * 100.)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_wrap_list,
+              definitions::ALL_GRADIENT_WRAPS,
+              Some(mapping.wrap),
+              |wrap| Message::Image(ImgMsg::WrapPicked(wrap)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_transform_list,
+              definitions::ALL_INDEX_TRANSFORMS,
+              Some(mapping.transform),
+              |transform| Message::Image(ImgMsg::TransformPicked(transform)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // repeats go by 1/4 hence the mess with 4.
+            &mut self.repeat_slider,
+            1.0..=256.0,
+            (mapping.repeat * 4.) as f32,
+            |val| Message::Image(ImgMsg::ChangeRepeat(val / 4.)),
+          ))
+          .push(
+            Text::new(format!(
+              "repeat: {:.2} (period {:.0} steps)",
+              mapping.repeat,
+              frac_args.steps as f64 / mapping.repeat
+            ))
+            .size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // offsets go by 1/100 hence the mess with 100.
+            &mut self.offset_slider,
+            0.0..=100.0,
+            (mapping.offset * 100.) as f32,
+            |val| Message::Image(ImgMsg::ChangeOffset(val / 100.)),
+          ))
+          .push(Text::new(format!("offset: {:.2}", mapping.offset)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(app_state.cycling, "Cycle Palette", |cycling| {
+            Message::App(AppMsg::ToggleCycling(cycling))
+          }))
+          .push(Slider::new(
+            // speeds go by 1/100 hence the mess with 100.
+            &mut self.cycle_speed_slider,
+            1.0..=100.0,
+            (app_state.cycle_speed * 100.) as f32,
+            |val| Message::App(AppMsg::ChangeCycleSpeed(val / 100.)),
+          ))
+          .push(Text::new(format!("speed: {:.2}", app_state.cycle_speed)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_lighting_list,
+              definitions::ALL_LIGHTING_MODELS,
+              Some(lighting.model),
+              |model| Message::Image(ImgMsg::LightingPicked(model)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_height_source_list,
+              definitions::ALL_HEIGHT_SOURCES,
+              Some(lighting.source),
+              |source| Message::Image(ImgMsg::HeightSourcePicked(source)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            &mut self.azimuth_slider,
+            0.0..=360.0,
+            lighting.azimuth as f32,
+            |val| Message::Image(ImgMsg::ChangeAzimuth(val)),
+          ))
+          .push(Text::new(format!("azimuth: {:.0}°", lighting.azimuth)).size(24))
+          .push(Slider::new(
+            &mut self.elevation_slider,
+            0.0..=90.0,
+            lighting.elevation as f32,
+            |val| Message::Image(ImgMsg::ChangeElevation(val)),
+          ))
+          .push(Text::new(format!("elevation: {:.0}°", lighting.elevation)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // heights go by 1/10 hence the mess with 10.
+            &mut self.height_slider,
+            1.0..=500.0,
+            (lighting.height * 10.) as f32,
+            |val| Message::Image(ImgMsg::ChangeHeight(val / 10.)),
+          ))
+          .push(Text::new(format!("height: {:.1}", lighting.height)).size(24))
+          .push(Slider::new(
+            // ambient light goes by 1/100 hence the mess with 100.
+            &mut self.ambient_slider,
+            0.0..=100.0,
+            (lighting.ambient * 100.) as f32,
+            |val| Message::Image(ImgMsg::ChangeAmbient(val / 100.)),
+          ))
+          .push(Text::new(format!("ambient: {:.2}", lighting.ambient)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // highlights go by 1/100 hence the mess with 100.
+            &mut self.specular_slider,
+            0.0..=100.0,
+            (lighting.specular * 100.) as f32,
+            |val| Message::Image(ImgMsg::ChangeSpecular(val / 100.)),
+          ))
+          .push(Text::new(format!("specular: {:.2}", lighting.specular)).size(24))
+          .push(Slider::new(
+            &mut self.shininess_slider,
+            1.0..=100.0,
+            lighting.shininess as f32,
+            |val| Message::Image(ImgMsg::ChangeShininess(val)),
+          ))
+          .push(Text::new(format!("shininess: {:.0}", lighting.shininess)).size(24)),
+      )
+      .push(self.layer_editor.view(&img_args.layers, layer_presets))
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(adjustment_slider(
+            &mut self.black_level_slider, "black", 0., 1., post.black_level, Adjustment::BlackLevel,
+          ))
+          .push(adjustment_slider(
+            &mut self.white_level_slider, "white", 0., 1., post.white_level, Adjustment::WhiteLevel,
+          )),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(adjustment_slider(
+            &mut self.brightness_slider, "brightness", -1., 1., post.brightness, Adjustment::Brightness,
+          ))
+          .push(adjustment_slider(
+            &mut self.contrast_slider, "contrast", 0., 3., post.contrast, Adjustment::Contrast,
+          )),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(adjustment_slider(
+            &mut self.gamma_slider, "gamma", 0.1, 4., post.gamma, Adjustment::Gamma,
+          ))
+          .push(adjustment_slider(
+            &mut self.hue_shift_slider, "hue", -180., 180., post.hue_shift, Adjustment::HueShift,
+          )),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(adjustment_slider(
+            &mut self.saturation_slider, "saturation", 0., 3., post.saturation, Adjustment::Saturation,
+          ))
+          .push(adjustment_slider(
+            &mut self.sharpen_slider, "sharpen", 0., 3., post.sharpen, Adjustment::Sharpen,
+          )),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(adjustment_slider(
+            &mut self.vignette_slider, "vignette", 0., 1., post.vignette, Adjustment::Vignette,
+          ))
+          .push(
+            PickList::new(
+              &mut self.change_dither_list,
+              definitions::ALL_DITHERS,
+              Some(post.dither),
+              |dither| Message::Image(ImgMsg::DitherPicked(dither)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(transparency.interior, "Transparent Interior", |transparent| {
+            Message::Image(ImgMsg::ToggleTransparentInterior(transparent))
+          }))
+          .push(Checkbox::new(transparency.exterior, "Transparent Exterior", |transparent| {
+            Message::Image(ImgMsg::ToggleTransparentExterior(transparent))
+          })),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(transparency.fade, "Fade", |fade| {
+            Message::Image(ImgMsg::ToggleFade(fade))
+          }))
+          .push(
+            PickList::new(
+              &mut self.change_fade_list,
+              algorithms::LAYER_ALGORITHMS,
+              Some(transparency.fade_algorithm),
+              |algorithm| Message::Image(ImgMsg::FadePicked(algorithm)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(Checkbox::new(transparency.invert_fade, "Invert", |invert| {
+            Message::Image(ImgMsg::ToggleInvertFade(invert))
+          })),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // powers go by 1/10 hence the mess with 10.
+            &mut self.fade_power_slider,
+            1.0..=50.0,
+            (transparency.fade_power * 10.) as f32,
+            |val| Message::Image(ImgMsg::ChangeFadePower(val / 10.)),
+          ))
+          .push(Text::new(format!("fade power: {:.1}", transparency.fade_power)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // densities go by 1/10 hence the mess with 10.
+            &mut self.density_slider,
+            1.0..=100.0,
+            (mapping.density * 10.) as f32,
+            |val| Message::Image(ImgMsg::ChangeDensity(val / 10.)),
+          ))
+          .push(Text::new(format!("density: {:.1}", mapping.density)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_space_list,
+              definitions::ALL_COLOR_SPACES,
+              Some(blend.space),
+              |space| Message::Image(ImgMsg::SpacePicked(space)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_hue_path_list,
+              definitions::ALL_HUE_PATHS,
+              Some(blend.hue_path),
+              |path| Message::Image(ImgMsg::HuePathPicked(path)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_curve_list,
+              definitions::ALL_GRADIENT_CURVES,
+              Some(blend.curve),
+              |curve| Message::Image(ImgMsg::CurvePicked(curve)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(format!(
+            "Constant: {:.10} + {:.10} i",
+            frac_args.c_re, frac_args.c_im
+          ))),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(format!(
+            "Center: {:.10} + {:.10} i",
+            frac_args.field.center_im, frac_args.field.center_re
+          ))),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(format!("Radius: {:.10}", frac_args.field.radius))),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(format!("Steps: {}", frac_args.steps))),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(format!(
+            "Pixel width: {}",
+            frac_args.field.pixel_size
+          ))),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Text::new(match img_args.seed {
+            Some(seed) => format!("Gradient seed: {} ({})", seed.seed, seed.scheme),
+            None => String::from("Gradient seed: none"),
+          })),
+      );
+
+    Scrollable::new(&mut self.scroll)
+      .push(options)
+      .height(Length::Units(850))
+  }
+}
+
+impl<'a> GradientEditor {
+  fn view(&mut self, gradient: &Gradient) -> Element<Message> {
+    let row_space = 10;
+    let row_pad = 10;
+    let strip_width = 440;
+
+    let selected = self.selected.min(gradient.peaks.len() + 1);
+    let is_peak = selected > 0 && selected <= gradient.peaks.len();
+    let (b, g, r, a) = selected_color(gradient, selected);
+    let hsv = space::to_space(ColorSpace::Hsv, (b, g, r, a));
+    let at = selected_at(gradient, selected);
+    let label = if selected == 0 {
+      String::from("Start color")
+    } else if is_peak {
+      format!("Peak at {:.3}", at)
+    } else {
+      String::from("End color")
+    };
+
+    let preview = Image::new(image::Handle::from_pixels(
+      strip_width,
+      32,
+      gradient_strip(gradient, strip_width, 32),
+    ))
+    .width(Length::Units(strip_width as u16))
+    .height(Length::Units(32));
+    self.peak_markers.markers = selection_order(gradient)
+      .into_iter()
+      .map(|marker| (marker, selected_at(gradient, marker)))
+      .collect();
+    self.peak_markers.selected = selected;
+    self.peak_markers.peaks = gradient.peaks.len();
+    let markers = Canvas::new(&mut self.peak_markers)
+      .width(Length::Units(strip_width as u16))
+      .height(Length::Units(16));
+
+    let mut remove_button = button(&mut self.remove_peak_button, "Remove Peak");
+    if is_peak {
+      remove_button = remove_button.on_press(Message::Image(ImgMsg::RemovePeak));
+    }
+    let mut at_row = Row::new().padding(row_pad).spacing(row_space);
+    if is_peak {
+      at_row = at_row.push(Slider::new(
+        // positions go by 1/1000 hence the mess with 1000.
+        &mut self.at_slider,
+        0.0..=1000.0,
+        (at * 1000.) as f32,
+        |val| Message::Image(ImgMsg::ChangePeakAt(val / 1000.)),
+      ));
+    }
+    let at_row = at_row.push(Text::new(label).size(24));
+
+    let editor = Column::new()
+      .padding(10)
+      .spacing(4)
+      .push(preview)
+      .push(markers)
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(button(&mut self.previous_peak_button, "◄").on_press(Message::Image(ImgMsg::PreviousPeak)))
+          .push(button(&mut self.next_peak_button, "►").on_press(Message::Image(ImgMsg::NextPeak)))
+          .push(button(&mut self.add_peak_button, "Add Peak").on_press(Message::Image(ImgMsg::AddPeak)))
+          .push(remove_button),
+      )
+      .push(at_row)
+      .push(channel_slider(&mut self.red_slider, "R", 255., r as f32, ColorChannel::Red))
+      .push(channel_slider(&mut self.green_slider, "G", 255., g as f32, ColorChannel::Green))
+      .push(channel_slider(&mut self.blue_slider, "B", 255., b as f32, ColorChannel::Blue))
+      .push(channel_slider(&mut self.alpha_slider, "A", 255., a as f32, ColorChannel::Alpha))
+      .push(channel_slider(
+        &mut self.hue_slider,
+        "H",
+        360.,
+        (hsv[0] * 360.) as f32,
+        ColorChannel::Hue,
+      ))
+      .push(channel_slider(
+        &mut self.saturation_slider,
+        "S",
+        100.,
+        (hsv[1] * 100.) as f32,
+        ColorChannel::Saturation,
+      ))
+      .push(channel_slider(
+        &mut self.value_slider,
+        "V",
+        100.,
+        (hsv[2] * 100.) as f32,
+        ColorChannel::Value,
+      ))
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(gradient.smooth, "Smooth", |smooth| {
+            Message::Image(ImgMsg::ToggleSmooth(smooth))
+          })),
+      );
+    Container::new(editor).into()
+  }
+}
+
+/// Markers are grabbed within this many pixels of their center.
+const MARKER_REACH: f32 = 8.;
+
+impl canvas::Program<Message> for PeakMarkers {
+  fn update(
+    &mut self,
+    event: canvas::Event,
+    bounds: Rectangle,
+    cursor: Cursor,
+  ) -> (canvas::event::Status, Option<Message>) {
+    let captured = canvas::event::Status::Captured;
+    match event {
+      canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+        let position = match cursor.position_in(&bounds) {
+          Some(position) => position,
+          None => return (canvas::event::Status::Ignored, None),
+        };
+        let closest = self
+          .markers
+          .iter()
+          .map(|&(marker, at)| (marker, (at as f32 * bounds.width - position.x).abs()))
+          .filter(|&(_, distance)| distance <= MARKER_REACH)
+          .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        match closest {
+          Some((marker, _)) => {
+            // Only peaks move, the start and end color stay at the ends
+            self.dragging = marker > 0 && marker <= self.peaks;
+            (captured, Some(Message::Image(ImgMsg::SelectPeak(marker))))
+          }
+          None => (canvas::event::Status::Ignored, None),
+        }
+      }
+      canvas::Event::Mouse(mouse::Event::CursorMoved { .. }) if self.dragging => {
+        // The cursor, unlike the event, accounts for scrolling
+        match cursor.position() {
+          Some(position) => {
+            let at = ((position.x - bounds.x) / bounds.width).max(0.).min(1.);
+            (captured, Some(Message::Image(ImgMsg::ChangePeakAt(at))))
+          }
+          None => (canvas::event::Status::Ignored, None),
+        }
+      }
+      canvas::Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) if self.dragging => {
+        self.dragging = false;
+        (captured, None)
+      }
+      _ => (canvas::event::Status::Ignored, None),
+    }
+  }
+
+  fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
+    let mut frame = Frame::new(bounds.size());
+    let (width, height) = (frame.width(), frame.height());
+    for &(marker, at) in self.markers.iter() {
+      // Triangles pointing up at the gradient, the selected one is white
+      let x = at as f32 * width;
+      let triangle = Path::new(|path| {
+        path.move_to(Point::new(x, 0.));
+        path.line_to(Point::new(x + height / 2., height));
+        path.line_to(Point::new(x - height / 2., height));
+        path.close();
+      });
+      let shade = if marker == self.selected { 1. } else { 0.5 };
+      frame.fill(&triangle, Color::from_rgb(shade, shade, shade));
+    }
+    vec![frame.into_geometry()]
+  }
+
+  fn mouse_interaction(&self, bounds: Rectangle, cursor: Cursor) -> mouse::Interaction {
+    if self.dragging {
+      mouse::Interaction::Grabbing
+    } else if cursor.is_over(&bounds) {
+      mouse::Interaction::Pointer
+    } else {
+      mouse::Interaction::default()
+    }
+  }
+}
+
+impl<'a> LayerEditor {
+  fn view(&mut self, layers: &[Layer], presets: Vec<String>) -> Element<Message> {
+    let row_space = 10;
+    let row_pad = 10;
+
+    let selected = self.selected.min(layers.len().saturating_sub(1));
+    let label = if layers.is_empty() {
+      String::from("No layers")
+    } else {
+      format!("Layer {} of {}", selected + 1, layers.len())
+    };
+
+    let mut remove_button = button(&mut self.remove_layer_button, "Remove Layer");
+    if !layers.is_empty() {
+      remove_button = remove_button.on_press(Message::Image(ImgMsg::RemoveLayer));
+    }
+    let mut editor = Column::new()
+      .padding(10)
+      .spacing(4)
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .align_items(Align::Center)
+          .push(button(&mut self.previous_layer_button, "◄").on_press(Message::Image(ImgMsg::PreviousLayer)))
+          .push(button(&mut self.next_layer_button, "►").on_press(Message::Image(ImgMsg::NextLayer)))
+          .push(button(&mut self.add_layer_button, "Add Layer").on_press(Message::Image(ImgMsg::AddLayer)))
+          .push(remove_button)
+          .push(Text::new(label).size(24)),
+      );
+
+    if let Some(layer) = layers.get(selected) {
+      editor = editor
+        .push(
+          Row::new().padding(row_pad).spacing(row_space).push(
+            PickList::new(
+              &mut self.change_algorithm_list,
+              algorithms::LAYER_ALGORITHMS,
+              Some(layer.algorithm),
+              |algorithm| Message::Image(ImgMsg::LayerAlgorithmPicked(algorithm)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+        )
+        .push(
+          Row::new()
+            .padding(row_pad)
+            .spacing(row_space)
+            .push(
+              PickList::new(
+                &mut self.change_blend_list,
+                definitions::ALL_BLEND_MODES,
+                Some(layer.blend),
+                |blend| Message::Image(ImgMsg::LayerBlendPicked(blend)),
+              )
+              .padding(8)
+              .text_size(24),
+            )
+            .push(
+              PickList::new(
+                &mut self.change_mask_list,
+                definitions::ALL_LAYER_MASKS,
+                Some(layer.mask),
+                |mask| Message::Image(ImgMsg::LayerMaskPicked(mask)),
+              )
+              .padding(8)
+              .text_size(24),
+            ),
+        )
+        .push(
+          Row::new()
+            .padding(row_pad)
+            .spacing(row_space)
+            .push(
+              PickList::new(
+                &mut self.change_gradient_list,
+                presets,
+                None,
+                |name| Message::Image(ImgMsg::LayerGradientPicked(name)),
+              )
+              .padding(8)
+              .text_size(24),
+            )
+            .push(Slider::new(
+              // opacities go by 1/100 hence the mess with 100.
+              &mut self.opacity_slider,
+              0.0..=100.0,
+              (layer.opacity * 100.) as f32,
+              |val| Message::Image(ImgMsg::ChangeLayerOpacity(val / 100.)),
+            ))
+            .push(Text::new(format!("opacity: {:.2}", layer.opacity)).size(24)),
+        );
+    }
+    Container::new(editor).into()
+  }
+}
+
+// -----------------------------------------------------------------------------
+// Export Layout
+// -----------------------------------------------------------------------------
+
+impl<'a> ExportLayout {
+  fn view(&'a mut self, export: &ExportArgs, preview_size: usize) -> Column<'a, Message> {
+    let row_space = 10;
+    let row_pad = 10;
+
+    Column::new()
+      .padding(10)
+      .spacing(10)
+      .push(
+        Row::new().padding(row_pad).spacing(row_space).push(
+          TextInput::new(
+            &mut self.directory_input,
+            "Directory",
+            &export.directory,
+            |directory| Message::Export(ExportMsg::DirectoryChanged(directory)),
+          )
+          .padding(8)
+          .size(24),
+        ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.name_input,
+              "File name",
+              &export.name,
+              |name| Message::Export(ExportMsg::NameChanged(name)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::App(AppMsg::SaveImage)),
+          )
+          .push(
+            PickList::new(
+              &mut self.change_naming_list,
+              definitions::ALL_FILE_NAMINGS,
+              Some(export.naming),
+              |naming| Message::Export(ExportMsg::NamingPicked(naming)),
+            )
+            .padding(8)
+            .text_size(24),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_format_list,
+              definitions::ALL_EXPORT_FORMATS,
+              Some(export.format),
+              |format| Message::Export(ExportMsg::FormatPicked(format)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(Slider::new(
+            &mut self.quality_slider,
+            1.0..=100.0,
+            export.jpeg_quality as f32,
+            |val| Message::Export(ExportMsg::ChangeQuality(val)),
+          ))
+          .push(Text::new(format!("JPEG quality: {}", export.jpeg_quality)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(export.sixteen_bit, "16 bit PNG and TIFF", |sixteen_bit| {
+            Message::Export(ExportMsg::ToggleSixteenBit(sixteen_bit))
+          })),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Slider::new(
+            // sizes go by 64 hence the mess with 64.
+            &mut self.size_slider,
+            2.0..=128.0,
+            (export.pixel_size / 64) as f32,
+            |val| Message::Export(ExportMsg::ChangeSize(val * 64.)),
+          ))
+          .push(Text::new(format!("size: {0} x {0}", export.pixel_size)).size(24)),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(Checkbox::new(export.at_preview, "At preview resolution", |at_preview| {
+            Message::Export(ExportMsg::TogglePreviewSize(at_preview))
+          })),
+      )
+      .push(
+        Row::new().padding(row_pad).spacing(row_space).push(Text::new(
+          if export.at_preview || export.pixel_size == preview_size {
+            format!("Colors the shown fractal at {0} x {0}", preview_size)
+          } else {
+            format!("Renders anew at {0} x {0}", export.pixel_size)
+          },
+        )),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            PickList::new(
+              &mut self.change_data_format_list,
+              definitions::ALL_DATA_FORMATS,
+              Some(export.data_format),
+              |format| Message::Export(ExportMsg::DataFormatPicked(format)),
+            )
+            .padding(8)
+            .text_size(24),
+          )
+          .push(
+            button(&mut self.export_data_button, "Export Data")
+              .on_press(Message::App(AppMsg::ExportData)),
+          ),
+      )
+      .push(
+        Row::new()
+          .padding(row_pad)
+          .spacing(row_space)
+          .push(
+            TextInput::new(
+              &mut self.data_path_input,
+              "Raw data file",
+              &self.data_path,
+              |path| Message::Export(ExportMsg::DataPathChanged(path)),
+            )
+            .padding(8)
+            .size(24)
+            .on_submit(Message::App(AppMsg::LoadData)),
+          )
+          .push(
+            button(&mut self.load_data_button, "Load Data")
+              .on_press(Message::App(AppMsg::LoadData)),
+          ),
       )
   }
 }
@@ -1,19 +1,27 @@
 //==============================================================================
 // Open crates and libraries
 //==============================================================================
+use std::sync::Arc;
+
 use fractal::definitions::*;
 use fractal::draw;
-use fractal::color;
+use fractal::render;
+use fractal::texture::TextureHits;
+use fractal::library;
+use fractal::library::PresetLibrary;
 
 //==============================================================================
 // Structs for keeping args and image data
 //==============================================================================
 
 /// Describes the fractal. Includes the fractals arguments and a rendered raw
-///  fractal picture to avoid redrawing unless necessary.
+///  fractal picture to avoid redrawing unless necessary, as well as the
+///  texture hits of its orbits, which are as slow to trace. The picture is
+///  shared with exports running in the background.
 pub struct FractalState{
   pub args : FracArgs,
-  pub fractal : RawFrac,
+  pub fractal : Arc<RawFrac>,
+  pub texture_hits : Option<TextureHits>,
 }
 
 impl Default for FractalState{
@@ -21,17 +29,26 @@ impl Default for FractalState{
     let default_args = FracArgs::default(); 
     FractalState{
       args : default_args,
-      fractal : draw::compute_fractal(default_args)
+      fractal : Arc::new(draw::compute_fractal(default_args)),
+      texture_hits : None,
     }
   }
 }
 
 /// Describes the image. Includes the coloring arguments and a colored picture
-/// to avoid redrawing unless necessary.
+/// to avoid redrawing unless necessary, as well as the gradient presets, the
+/// names of the last picked ones, the settings for extracting a gradient from
+/// a photo, the scheme used for random gradients and the export settings.
 pub struct ImageState{
   pub args : ImageArgs,
   pub image : FracImage,
-  pub current_preset : GradientPreset,
+  pub presets : PresetLibrary,
+  pub current_preset : String,
+  pub current_interior_preset : String,
+  pub extract_colors : usize,
+  pub extract_order : PaletteOrder,
+  pub random_scheme : ColorScheme,
+  pub export : ExportArgs,
 }
 
 impl Default for ImageState{
@@ -39,13 +56,20 @@ impl Default for ImageState{
     let default_frac_state = FractalState::default();
     // draw and color default fractal
     let frac = default_frac_state.fractal;
-    let steps = default_frac_state.args.steps;
+    let frac_args = default_frac_state.args;
     let img_args = ImageArgs::default();
-    let gradient = img_args.gradient.clone();
+    let image = render::color_image(&frac, &frac_args, &img_args, &mut None)
+      .expect("default fractal has the size of its arguments");
     // create
     ImageState{
       args : img_args,
-      current_preset : DEFAULT_GRADIENT_PRESET,
-      image : color::color_fractal(&frac, steps, gradient)}
+      presets : PresetLibrary::load(library::PRESET_DIRECTORY),
+      current_preset : library::DEFAULT_PRESET.to_string(),
+      current_interior_preset : "Azul".to_string(),
+      extract_colors : 8,
+      extract_order : PaletteOrder::Luminance,
+      random_scheme : ColorScheme::Analogous,
+      export : ExportArgs::default(),
+      image : image}
   }
 }